@@ -0,0 +1,86 @@
+use std::fs;
+use std::hint::black_box;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use minigrep::output::{OutputMode, Printer};
+use minigrep::parallel::{self, ReadOptions};
+use minigrep::{stream, Matcher, Searcher};
+
+// `cargo bench` compares the ways a literal query can be searched for, on poem.txt repeated until
+// it's the size of a small, a medium and a big log:
+//   book       contents.lines() and line.contains() into a Vec, the search from the book
+//   searcher   Searcher::search, which scans the whole text with memchr's memmem
+//   stream     the file read and searched line by line (what stdin and -A/-B/-C get)
+//   read       the file read into memory and scanned as a whole (--no-mmap)
+//   mmap       the file memory-mapped and scanned as a whole (the default for big files)
+// for a query that is on every few lines ("nobody"), one that's rarer ("admiring") and one that
+// isn't there at all ("zebra"). Each one is run for about half a second and the fastest run counts.
+
+const COPIES: [usize; 3] = [1_000, 10_000, 100_000];
+const QUERIES: [&str; 3] = ["nobody", "admiring", "zebra"];
+
+//one way of searching, giving back how many lines it found
+type Method<'a> = (&'static str, Box<dyn FnMut() -> usize + 'a>);
+
+fn book_search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+  contents.lines().filter(|line| line.contains(query)).collect()
+}
+
+//the fastest of as many runs as fit in half a second (at least three), and the number of hits
+fn time(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+  let mut best = Duration::MAX;
+  let mut hits = 0;
+  let started = Instant::now();
+  let mut runs = 0;
+  while runs < 3 || started.elapsed() < Duration::from_millis(500) {
+    let start = Instant::now();
+    hits = black_box(run());
+    best = best.min(start.elapsed());
+    runs += 1;
+  }
+  (best, hits)
+}
+
+fn search_file(matcher: &Matcher, path: &PathBuf, mmap: bool) -> usize {
+  let mut printer = Printer::new(false, false, 0, 0);
+  printer.mode = OutputMode::Count;
+  parallel::search_files(matcher, None, &mut printer, std::slice::from_ref(path), 1, ReadOptions { mmap, decompress: false }, &mut io::sink()).unwrap();
+  printer.summary().matched_lines
+}
+
+fn main() {
+  let poem = include_str!("../poem.txt");
+  let dir = std::env::temp_dir().join(format!("minigrep-bench-{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+
+  println!("{:>8} {:>10} {:>9} {:>8} {:>10} {:>10}", "size", "query", "method", "hits", "time", "MB/s");
+  for copies in COPIES {
+    let contents = format!("{poem}\n").repeat(copies);
+    let path = dir.join(format!("poem-{copies}.txt"));
+    fs::write(&path, &contents).unwrap();
+    let size = contents.len();
+
+    for query in QUERIES {
+      let matcher = Matcher::sensitive(query);
+      let searcher = Searcher::new(query);
+      let methods: [Method; 5] = [
+        ("book", Box::new(|| book_search(query, &contents).len())),
+        ("searcher", Box::new(|| searcher.search(&contents).count())),
+        ("stream", Box::new(|| stream::search_stream(&matcher, BufReader::new(fs::File::open(&path).unwrap())).count())),
+        ("read", Box::new(|| search_file(&matcher, &path, false))),
+        ("mmap", Box::new(|| search_file(&matcher, &path, true))),
+      ];
+      let mut expected = None;
+      for (name, run) in methods {
+        let (best, hits) = time(run);
+        //all of them have to find the same lines, or the comparison means nothing
+        assert_eq!(*expected.get_or_insert(hits), hits, "{name} found a different number of lines");
+        let throughput = size as f64 / best.as_secs_f64() / 1e6;
+        println!("{:>7}K {query:>10} {name:>9} {hits:>8} {:>8.2}ms {throughput:>10.0}", size / 1024, best.as_secs_f64() * 1e3);
+      }
+    }
+  }
+  fs::remove_dir_all(&dir).unwrap();
+}
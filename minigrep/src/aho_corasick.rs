@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use crate::casefold::{self, Fold};
+
+// Aho–Corasick automaton for searching many literal patterns in one pass over a line.
+// The patterns are stored in a trie; every state also gets a failure link to the longest proper
+// suffix of its path that is also in the trie, so when a character doesn't continue the current
+// path we fall back along those links instead of rescanning the line once per pattern.
+// It works on chars rather than bytes so -i can fold each character as it is read. A character
+// can fold into several ('ß' into "ss"), so the automaton runs over the folded characters and a
+// match only counts when it starts and ends on the edges of characters of the original line.
+
+#[derive(Debug, Clone, Default)]
+struct State {
+  next: Vec<(char, usize)>, //sorted by char, so lookups can binary search
+  fail: usize,
+  out: Vec<usize>, //patterns that end here, including the ones inherited through the failure link
+}
+
+impl State {
+  fn goto(&self, c: char) -> Option<usize> {
+    self.next.binary_search_by_key(&c, |&(k, _)| k).ok().map(|i| self.next[i].1)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+  states: Vec<State>,
+  lens: Vec<usize>, //length of each pattern in (folded) chars
+  max_len: usize,
+  has_empty: bool,
+  ignore_case: bool,
+}
+
+impl AhoCorasick {
+  pub fn new(patterns: &[String], ignore_case: bool) -> AhoCorasick {
+    let mut states = vec![State::default()];
+    let mut lens = Vec::new();
+
+    for (index, pattern) in patterns.iter().enumerate() {
+      let pattern: Vec<char> = if ignore_case { casefold::fold_str(pattern) } else { pattern.chars().collect() };
+      let mut state = 0;
+      for &c in &pattern {
+        state = match states[state].goto(c) {
+          Some(next) => next,
+          None => {
+            states.push(State::default());
+            let next = states.len() - 1;
+            let at = states[state].next.partition_point(|&(k, _)| k < c);
+            states[state].next.insert(at, (c, next));
+            next
+          }
+        };
+      }
+      states[state].out.push(index);
+      lens.push(pattern.len());
+    }
+
+    //breadth first, so a state's failure target is always finished before the state itself
+    let mut queue: VecDeque<usize> = states[0].next.iter().map(|&(_, s)| s).collect();
+    while let Some(state) = queue.pop_front() {
+      for (c, child) in states[state].next.clone() {
+        let mut fail = states[state].fail;
+        let target = loop {
+          if let Some(next) = states[fail].goto(c) {
+            break next;
+          }
+          if fail == 0 {
+            break 0;
+          }
+          fail = states[fail].fail;
+        };
+        states[child].fail = target;
+        let inherited = states[target].out.clone();
+        states[child].out.extend(inherited);
+        queue.push_back(child);
+      }
+    }
+
+    AhoCorasick {
+      states,
+      max_len: lens.iter().copied().max().unwrap_or(0),
+      has_empty: lens.contains(&0),
+      lens,
+      ignore_case,
+    }
+  }
+
+  pub fn find_at(&self, text: &str, at: usize) -> Option<(usize, usize)> {
+    self.find_at_where(text, at, |_, _| true)
+  }
+
+  //leftmost match starting at or after `at`, preferring the longest pattern when several start
+  //at the same place; only spans that `accept` agrees with count, which is how -w and -x are checked
+  //without missing a shorter pattern hiding behind a rejected longer one
+  pub fn find_at_where(&self, text: &str, at: usize, accept: impl Fn(usize, usize) -> bool) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let consider = |start: usize, end: usize, best: &mut Option<(usize, usize)>| {
+      let better = match *best {
+        Some((s, e)) => start < s || (start == s && end > e),
+        None => true,
+      };
+      if better && accept(start, end) {
+        *best = Some((start, end));
+      }
+    };
+
+    if self.lens.is_empty() {
+      return None;
+    }
+    let mut state = 0;
+    //for each of the last max_len (folded) chars: the byte offset of the line character it came from,
+    //and whether it is the first char of that character's folding
+    let mut starts: VecDeque<(usize, bool)> = VecDeque::with_capacity(self.max_len + 1);
+    let mut chars = text[at..].char_indices().map(|(i, c)| (i + at, c)).peekable();
+
+    loop {
+      //an empty pattern matches right here before anything is read
+      let here = chars.peek().map_or(text.len(), |&(i, _)| i);
+      if self.has_empty {
+        consider(here, here, &mut best);
+      }
+      //anything found from now on ends further right and is at most max_len chars long, so it
+      //can't start before `earliest`: a match that starts earlier than that can't be beaten any more
+      if let Some((start, _)) = best {
+        let earliest = if starts.len() < self.max_len {
+          at
+        } else if self.max_len >= 2 {
+          starts[1].0
+        } else {
+          here
+        };
+        if start < earliest {
+          return best;
+        }
+      }
+
+      let (i, original) = match chars.next() {
+        Some(next) => next,
+        None => return best,
+      };
+      let end = i + original.len_utf8();
+      let folded = if self.ignore_case { casefold::fold(original) } else { Fold::unchanged(original) };
+      let last = folded.len() - 1;
+      for (k, c) in folded.enumerate() {
+        starts.push_back((i, k == 0));
+        if starts.len() > self.max_len {
+          starts.pop_front();
+        }
+
+        state = loop {
+          if let Some(next) = self.states[state].goto(c) {
+            break next;
+          }
+          if state == 0 {
+            break 0;
+          }
+          state = self.states[state].fail;
+        };
+
+        //a pattern ending halfway through a character's folding isn't a match ("s" in 'ß')
+        if k < last {
+          continue;
+        }
+        for &pattern in &self.states[state].out {
+          let len = self.lens[pattern];
+          if len == 0 {
+            continue; //already considered before the character was read
+          }
+          let (start, whole) = starts[starts.len() - len];
+          if whole {
+            consider(start, end, &mut best);
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn patterns(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn leftmost_then_longest() {
+    let ac = AhoCorasick::new(&patterns(&["bog", "admiring", "an", "an admiring"]), false);
+    assert_eq!(Some((3, 14)), ac.find_at("To an admiring bog!", 0));
+    assert_eq!(Some((15, 18)), ac.find_at("To an admiring bog!", 14));
+    assert_eq!(None, ac.find_at("nothing here", 0));
+  }
+
+  #[test]
+  fn failure_links_and_case() {
+    let ac = AhoCorasick::new(&patterns(&["he", "she", "hers"]), true);
+    assert_eq!(Some((1, 4)), ac.find_at("uSHErs", 0));
+    assert_eq!(Some((2, 6)), ac.find_at("ushers", 2));
+
+    let ac = AhoCorasick::new(&patterns(&["strasse", "σ"]), true);
+    assert_eq!(Some((4, 11)), ac.find_at("Die Straße", 0));
+    assert_eq!(Some((0, 2)), ac.find_at("ς", 0));
+    assert_eq!(None, AhoCorasick::new(&patterns(&["s", "x"]), true).find_at("ß", 0));
+  }
+
+  #[test]
+  fn accept_filter_falls_back_to_shorter_patterns() {
+    let ac = AhoCorasick::new(&patterns(&["a", "a-b"]), false);
+    let text = "a-bc";
+    let word = |start: usize, end: usize| {
+      !text[..start].ends_with(char::is_alphanumeric) && !text[end..].starts_with(char::is_alphanumeric)
+    };
+    assert_eq!(Some((0, 3)), ac.find_at(text, 0));
+    assert_eq!(Some((0, 1)), ac.find_at_where(text, 0, word));
+  }
+
+  #[test]
+  fn empty_and_no_patterns() {
+    assert_eq!(Some((2, 2)), AhoCorasick::new(&patterns(&[""]), false).find_at("abc", 2));
+    assert_eq!(None, AhoCorasick::new(&[], false).find_at("abc", 0));
+  }
+}
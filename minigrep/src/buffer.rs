@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+
+use memchr::memmem::Finder;
+use memmap2::Mmap;
+
+// The fast path for a literal, case sensitive query: instead of going line by line and searching
+// each line, the whole file (or string) is scanned for the query in one go with memchr's memmem,
+// which uses SIMD where the CPU has it and falls back on the two-way algorithm. Lines are only
+// split out around the places the query was found, and the line numbers in between are counted
+// with a vectorised count of the '\n' bytes, so a log where one line in a million matches costs
+// little more than reading it.
+// Files are memory-mapped when they're big enough for that to pay off, and read into memory
+// otherwise. A mapped file that another program truncates while we're reading it can bring the
+// whole process down with SIGBUS, which is why --no-mmap exists.
+
+//files smaller than this are read with a plain read(): mapping has a fixed cost that only pays
+//off when there are enough pages to save copying
+const MMAP_THRESHOLD: u64 = 64 * 1024;
+
+//the contents of a whole file, mapped or read
+pub enum Contents {
+  Mapped(Mmap),
+  Read(Vec<u8>),
+}
+
+impl Deref for Contents {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      Contents::Mapped(map) => map,
+      Contents::Read(bytes) => bytes,
+    }
+  }
+}
+
+//None when the file isn't a regular file (a FIFO, or a /proc file that says it's empty),
+//those have to be streamed
+pub fn load(mut file: &File, mmap: bool) -> io::Result<Option<Contents>> {
+  let metadata = file.metadata()?;
+  if !metadata.is_file() || metadata.len() == 0 {
+    return Ok(None);
+  }
+  if mmap && metadata.len() >= MMAP_THRESHOLD {
+    // SAFETY: the map is only read, and only while the file is being searched. If the file is
+    // changed meanwhile we may see a mix of old and new contents, which grep would too; if it's
+    // truncated, reading past the new end raises SIGBUS (see --no-mmap).
+    let map = unsafe { Mmap::map(file)? };
+    return Ok(Some(Contents::Mapped(map)));
+  }
+  let mut bytes = Vec::with_capacity(metadata.len() as usize);
+  file.read_to_end(&mut bytes)?;
+  Ok(Some(Contents::Read(bytes)))
+}
+
+//where a candidate line is in the buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CandidateLine {
+  pub number: usize, //counted from 1
+  pub start: usize, //byte offset of the line in the buffer
+  pub end: usize, //where its text ends, without the "\n" or "\r\n"
+  pub skipped: (usize, usize), //the bytes between the previous candidate line and this one
+}
+
+//the lines of a buffer that contain `needle`, in order, split the same way as str::lines();
+//every line is given once, however many times the needle is in it. The needle can't have a '\n'
+//in it, it would find places that aren't in any one line
+pub struct CandidateLines<'a> {
+  finder: Finder<'a>,
+  haystack: &'a [u8],
+  next: usize, //where the search goes on: the start of the line after the last candidate
+  line_number: usize, //of the line starting at `next`
+}
+
+impl<'a> CandidateLines<'a> {
+  pub fn new(haystack: &'a [u8], needle: &'a str) -> CandidateLines<'a> {
+    CandidateLines { finder: Finder::new(needle.as_bytes()), haystack, next: 0, line_number: 1 }
+  }
+}
+
+impl Iterator for CandidateLines<'_> {
+  type Item = CandidateLine;
+
+  fn next(&mut self) -> Option<CandidateLine> {
+    let rest = self.haystack.get(self.next..)?;
+    let hit = self.next + self.finder.find(rest)?;
+    let start = memchr::memrchr(b'\n', &self.haystack[self.next..hit]).map_or(self.next, |i| self.next + i + 1);
+    let newline = memchr::memchr(b'\n', &self.haystack[hit..]).map(|i| hit + i);
+    let number = self.line_number + memchr::memchr_iter(b'\n', &self.haystack[self.next..start]).count();
+    let skipped = (self.next, start);
+
+    let mut end = newline.unwrap_or(self.haystack.len());
+    if newline.is_some() && end > start && self.haystack[end - 1] == b'\r' {
+      end -= 1;
+    }
+    self.next = newline.map_or(self.haystack.len(), |newline| newline + 1);
+    self.line_number = number + 1;
+    Some(CandidateLine { number, start, end, skipped })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn splits_lines_only_around_hits() {
+    let text = "I'm nobody! Who are you?\r\nAre you nobody, too?\nThen there's a pair of us\n\nnobody";
+    let lines: Vec<(usize, &str)> = CandidateLines::new(text.as_bytes(), "nobody").map(|line| (line.number, &text[line.start..line.end])).collect();
+    assert_eq!(vec![(1, "I'm nobody! Who are you?"), (2, "Are you nobody, too?"), (5, "nobody")], lines);
+
+    let skipped: Vec<(usize, usize)> = CandidateLines::new(text.as_bytes(), "nobody").map(|line| line.skipped).collect();
+    assert_eq!(vec![(0, 0), (26, 26), (47, 74)], skipped);
+
+    //the line numbers have to agree with str::lines() wherever the hits are
+    let poem = include_str!("../poem.txt").repeat(3);
+    for needle in ["o", "bog", "!", "How", "Frog"] {
+      let expected: Vec<usize> = poem.lines().enumerate().filter(|(_, line)| line.contains(needle)).map(|(i, _)| i + 1).collect();
+      let found: Vec<usize> = CandidateLines::new(poem.as_bytes(), needle).map(|line| line.number).collect();
+      assert_eq!(expected, found, "{needle:?}");
+    }
+  }
+}
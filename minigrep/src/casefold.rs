@@ -0,0 +1,165 @@
+use std::iter::FusedIterator;
+
+// Unicode case folding for -i.
+// Lowercasing alone isn't enough to compare text without case: 'ß' uppercases to "SS", so "STRASSE"
+// and "straße" only meet if both sides are taken to the same form, and Greek has two lowercase
+// sigmas (σ and the word-final ς) for a single uppercase Σ. Folding a character means lowercasing
+// its uppercase form, which handles both. That's the language-neutral folding of the Unicode
+// CaseFolding table: 'İ' (dotted capital I) folds to "i̇" (i + U+0307 combining dot) and the Turkish
+// dotless 'ı' stays itself, so neither of them turns into a plain 'i'.
+// Everything here works one character at a time and never allocates, so a line can be compared
+// against a folded query without building a lowercased copy of it.
+
+//no character folds to more than 3 characters (e.g. 'ΐ' is ι + two combining marks)
+const MAX_FOLD: usize = 3;
+
+//the folded form of one character
+#[derive(Debug, Clone)]
+pub struct Fold {
+  chars: [char; MAX_FOLD],
+  len: usize,
+  next: usize,
+}
+
+impl Fold {
+  //a character left as it is, for the case sensitive searches
+  pub fn unchanged(c: char) -> Fold {
+    Fold { chars: [c, '\0', '\0'], len: 1, next: 0 }
+  }
+
+  fn push(&mut self, c: char) {
+    self.chars[self.len] = c;
+    self.len += 1;
+  }
+}
+
+impl Iterator for Fold {
+  type Item = char;
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.chars[..self.len].get(self.next).copied();
+    self.next += 1;
+    c
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let left = self.len.saturating_sub(self.next);
+    (left, Some(left))
+  }
+}
+
+impl ExactSizeIterator for Fold {}
+impl FusedIterator for Fold {}
+
+//full case folding of `c`: "ß" and "ẞ" give "ss", 'Σ' and 'ς' give 'σ'
+pub fn fold(c: char) -> Fold {
+  if c.is_ascii() {
+    return Fold::unchanged(c.to_ascii_lowercase());
+  }
+  //'ı' uppercases to a plain 'I', which would fold it into 'i'
+  if c == 'ı' {
+    return Fold::unchanged(c);
+  }
+  //the second round takes 'ẞ', which lowercases to 'ß', on to "ss"
+  let mut folded = Fold { chars: ['\0'; MAX_FOLD], len: 0, next: 0 };
+  let rounds = c.to_uppercase().flat_map(char::to_lowercase).flat_map(char::to_uppercase).flat_map(char::to_lowercase);
+  for f in rounds {
+    folded.push(f);
+  }
+  folded
+}
+
+//one-to-one folding for code that can only compare a character with a character (the regex
+//engine's Char instructions); characters whose full folding is longer fall back to their lowercase,
+//so 'ẞ' still meets 'ß' but neither meets "ss"
+pub(crate) fn fold_char(c: char) -> char {
+  let mut folded = fold(c);
+  if folded.len() == 1 {
+    return folded.next().unwrap_or(c);
+  }
+  let mut lower = c.to_lowercase();
+  match (lower.next(), lower.next()) {
+    (Some(l), None) => l,
+    _ => c,
+  }
+}
+
+//byte range of the first place in `line` at or after `at` whose folding is exactly `folded_query`;
+//a match has to cover whole characters of the line, so "s" doesn't match half of a 'ß'
+pub fn find_folded(line: &str, at: usize, folded_query: &[char]) -> Option<(usize, usize)> {
+  let mut starts = line[at..].char_indices().map(|(i, _)| i + at).chain(std::iter::once(line.len()));
+  starts.find_map(|start| matches_at(line, start, folded_query).map(|end| (start, end)))
+}
+
+//where the match of `folded_query` that starts at `start` ends, if there is one
+fn matches_at(line: &str, start: usize, folded_query: &[char]) -> Option<usize> {
+  let mut wanted = folded_query.iter();
+  let mut end = start;
+  while wanted.len() > 0 {
+    let c = line[end..].chars().next()?;
+    for folded in fold(c) {
+      if wanted.next() != Some(&folded) {
+        return None;
+      }
+    }
+    end += c.len_utf8();
+  }
+  Some(end)
+}
+
+//the query side of find_folded(), done once before searching
+pub fn fold_str(text: &str) -> Vec<char> {
+  text.chars().flat_map(fold).collect()
+}
+
+//-S: whether the query asks for a case sensitive search by containing an uppercase letter;
+//in a regex the letter after a backslash is syntax (\W, \S, \B), not text
+pub fn has_uppercase(pattern: &str, regex: bool) -> bool {
+  let mut chars = pattern.chars();
+  while let Some(c) = chars.next() {
+    if regex && c == '\\' {
+      chars.next();
+    } else if c.is_uppercase() {
+      return true;
+    }
+  }
+  false
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn folded(c: char) -> String {
+    fold(c).collect()
+  }
+
+  #[test]
+  fn full_folding() {
+    assert_eq!(("ss", "ss"), (folded('ß').as_str(), folded('ẞ').as_str()));
+    assert_eq!(("σ", "σ", "σ"), (folded('Σ').as_str(), folded('ς').as_str(), folded('σ').as_str()));
+    assert_eq!(("i\u{307}", "ı", "i"), (folded('İ').as_str(), folded('ı').as_str(), folded('I').as_str()));
+    assert_eq!(('ß', 'σ', 'İ'), (fold_char('ẞ'), fold_char('ς'), fold_char('İ')));
+  }
+
+  #[test]
+  fn finds_folded_spans_in_the_original_line() {
+    let query = fold_str("STRASSE");
+    assert_eq!(Some((4, 11)), find_folded("Die Straße", 0, &query));
+    assert_eq!(Some((4, 12)), find_folded("Die STRAẞE", 0, &fold_str("straße")));
+    assert_eq!(None, find_folded("Straße", 0, &fold_str("stras")));
+    assert_eq!(Some((0, 16)), find_folded("ΟΔΥΣΣΕΥΣ", 0, &fold_str("οδυσσευς")));
+    assert_eq!(None, find_folded("İstanbul", 0, &fold_str("istanbul")));
+    assert_eq!(Some((0, 9)), find_folded("İstanbul", 0, &fold_str("i\u{307}stanbul")));
+    assert_eq!(Some((7, 7)), find_folded("no case", 7, &[]));
+  }
+
+  #[test]
+  fn smart_case_uppercase() {
+    assert!(has_uppercase("Rust", false));
+    assert!(!has_uppercase("rust", false));
+    assert!(!has_uppercase(r"\w+\S", true));
+    assert!(has_uppercase(r"\w+\S", false));
+    assert!(has_uppercase("Σ", true));
+  }
+}
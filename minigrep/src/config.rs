@@ -0,0 +1,672 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::casefold;
+use crate::defaults::Defaults;
+use crate::error::Error;
+use crate::output::OutputMode;
+use crate::Boundary;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] QUERY [PATH...]
+       minigrep [OPTIONS] -e QUERY... [-f FILE...] [PATH...]
+
+Search for QUERY in each PATH. Directories are searched recursively.
+With no PATH, or when PATH is -, standard input is read.
+With -e or -f, every argument that isn't an option is a PATH.
+
+Options:
+  -s, --case-sensitive       match case exactly
+  -i, --ignore-case          ignore case
+  -S, --smart-case           ignore case unless QUERY has an uppercase letter
+  -e, --regexp QUERY         search for QUERY; repeat to search for any of several
+  -f, --file FILE            search for every line of FILE
+  -E, --regex                treat QUERY as a regular expression
+      --fuzzy K              match parts of lines at most K edits away from QUERY
+  -U, --multiline            let matches go over line breaks (\\n in a regex, or a QUERY with newlines)
+      --tui                  browse the hits full screen, refine QUERY as you type, Enter opens $EDITOR
+  -v, --invert-match         select the lines that don't match
+  -w, --word-regexp          only match whole words
+  -x, --line-regexp          only match whole lines
+  -n, --line-number          print the line number of each hit
+  -N, --no-line-number       don't, even if the defaults say so
+  -A, --after-context NUM    print NUM lines after each hit
+  -B, --before-context NUM   print NUM lines before each hit
+  -C, --context NUM          print NUM lines before and after each hit
+      --color[=WHEN]         highlight matches: auto (the default), always or never
+      --json                 print one JSON object per hit and a summary at the end
+  -m, --max-count NUM        stop reading a file after NUM matching lines
+  -c, --count                print only the number of matching lines in each file
+  -l, --files-with-matches   print only the names of files with a hit
+  -L, --files-without-match  print only the names of files without a hit
+  -q, --quiet                print nothing, only set the exit status
+      --watch                keep following the files, printing new hits as lines are written (Linux)
+      --stats                print what was searched and how long each phase took, on stderr
+  -j, --threads NUM          search NUM files at a time (default: one per CPU core)
+      --include GLOB         only search files matching GLOB (repeatable)
+      --exclude GLOB         skip files and directories matching GLOB (repeatable)
+      --hidden               search hidden files and directories too
+      --no-hidden            skip them, even if the defaults say otherwise
+      --no-ignore            don't skip what .gitignore and .ignore files list
+      --ignore               skip it, even if the defaults say otherwise
+  -a, --text                 search binary files as if they were text
+      --no-mmap              read big files instead of memory-mapping them
+  -z, --search-zip           search gzip, bzip2, xz and zstd files decompressed
+      --no-search-zip        search them as they are, even if the defaults say otherwise
+      --replace TEXT         print hits with each match replaced by TEXT ($1 is a group with -E)
+      --in-place             show the changes --replace would make to the files as a diff
+      --apply                with --in-place, write the changes into the files
+  -h, --help                 print this help and exit
+  -V, --version              print the version and exit
+      --                     stop parsing options, everything after is QUERY or PATH
+
+Defaults for --color, the case mode, -j, -n, --hidden, --no-ignore, -z, --include and
+--exclude can be set in ~/.config/minigrep/config.toml and overridden by MINIGREP_* environment variables
+(MINIGREP_CASE=smart, MINIGREP_COLOR=never, ...); options on the command line override both.
+IGNORE_CASE is the same as MINIGREP_CASE=insensitive.
+
+Exit status: 0 if a line matched, 1 if none did, 2 for bad arguments or settings, 3 if a pattern file
+couldn't be read, 4 for an invalid regex, 5 if --watch couldn't watch a path, 6 if some
+files or directories couldn't be read, 7 if the results couldn't be written and 8 if
+--in-place couldn't write a file back.";
+
+//--color=WHEN
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    #[default]
+    Auto, //only when stdout is a terminal and NO_COLOR isn't set
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    Smart, //-S: insensitive unless a pattern has an uppercase letter
+}
+
+impl CaseMode {
+    //the names used in the config file and MINIGREP_CASE
+    pub fn parse(value: &str) -> Option<CaseMode> {
+        match value {
+            "sensitive" => Some(CaseMode::Sensitive),
+            "insensitive" => Some(CaseMode::Insensitive),
+            "smart" => Some(CaseMode::Smart),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub patterns: Vec<String>, //a line matches if any of these does
+    pub file_paths: Vec<String>, //files or directories, directories are searched recursively; "-" is stdin
+    pub case_mode: Option<CaseMode>, //None when none of -s, -i and -S was given
+    pub default_case: Option<CaseMode>, //from the config file or the environment, for when case_mode is None
+    pub regex: bool,
+    pub invert_match: bool,
+    pub boundary: Boundary,
+    pub line_numbers: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub output_mode: OutputMode,
+    pub color: ColorChoice,
+    pub threads: usize, //-j; 0 means one thread per CPU core
+    pub include: Vec<String>, //globs, see walk::WalkOptions
+    pub exclude: Vec<String>,
+    pub hidden: bool,
+    pub no_ignore: bool,
+    pub text: bool,
+    pub mmap: bool, //off with --no-mmap
+    pub search_zip: bool, //-z
+    pub max_count: Option<usize>, //-m
+    pub fuzzy: Option<usize>, //--fuzzy K
+    pub multiline: bool, //-U
+    pub tui: bool,
+    pub watch: bool, //keep following the files after searching them, like tail -F
+    pub stats: bool,
+    pub replace: Option<String>, //--replace
+    pub in_place: bool,
+    pub apply: bool, //--in-place writes the files instead of showing a diff
+}
+
+//--help and --version are reported through the error path too: they stop the parsing just like a
+//bad argument, and main decides what to print and which exit code to use
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgsError {
+    Help,
+    Version,
+    MissingQuery, //no QUERY, and no -e or -f either
+    UnknownOption(String),
+    MissingValue(String), //the option that needs one
+    UnexpectedValue(String), //--option=value for an option that doesn't take a value
+    InvalidValue { option: String, value: String, expected: &'static str },
+    Requires { option: String, requirement: &'static str }, //an option that only makes sense with another
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "{USAGE}"),
+            ArgsError::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+            ArgsError::MissingQuery => write!(f, "Not enough arguments"),
+            ArgsError::UnknownOption(option) => write!(f, "unknown option '{option}'"),
+            ArgsError::MissingValue(option) => write!(f, "option '{option}' needs a value"),
+            ArgsError::UnexpectedValue(option) => write!(f, "option '{option}' doesn't take a value"),
+            ArgsError::InvalidValue { option, value, expected } => write!(f, "option '{option}' needs {expected}, got '{value}'"),
+            ArgsError::Requires { option, requirement } => write!(f, "option '{option}' needs {requirement}"),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+impl Config {
+    //the command line on top of the defaults from the config file and the environment
+    pub fn build(args: &[String]) -> Result<Config, Error> {
+        Config::build_with(args, Defaults::load()?)
+    }
+
+    //the command line on top of `defaults`: a flag always wins over a default, which is why the
+    //boolean ones have a flag to turn them off too (-N, --no-hidden, --ignore)
+    pub fn build_with(args: &[String], defaults: Defaults) -> Result<Config, Error> {
+        let mut positional = Vec::new();
+        let mut patterns = Vec::new();
+        let mut patterns_given = false; //-e or -f, even an empty pattern file, means no positional QUERY
+        let mut case_mode = None;
+        let mut regex = false;
+        let mut invert_match = false;
+        let mut word = false;
+        let mut whole_line = false;
+        let mut line_numbers = defaults.line_numbers.unwrap_or(false);
+        //like grep, -A and -B win over -C whatever order they come in, so -C is only used for the side
+        //that wasn't given
+        let mut before_context = None;
+        let mut after_context = None;
+        let mut context = None;
+        let mut output_mode = OutputMode::Lines;
+        let mut quiet = false;
+        let mut color = defaults.color.unwrap_or_default();
+        let mut threads = defaults.threads.unwrap_or(0);
+        let mut include = Vec::new(); //the defaults only count if the command line has none, see below
+        let mut exclude = defaults.exclude;
+        let mut hidden = defaults.hidden.unwrap_or(false);
+        let mut no_ignore = defaults.no_ignore.unwrap_or(false);
+        let mut text = false;
+        let mut mmap = true;
+        let mut search_zip = defaults.search_zip.unwrap_or(false);
+        let mut max_count = None;
+        let mut fuzzy = None;
+        let mut multiline = false;
+        let mut tui = false;
+        let mut watch = false;
+        let mut stats = false;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut apply = false;
+
+        //flags may come before, between or after the query and the paths
+        let mut parser = ArgParser::new(args.get(1..).unwrap_or_default());
+        while let Some(arg) = parser.next()? {
+            let flag = match arg {
+                Arg::Positional(value) => {
+                    positional.push(value);
+                    continue;
+                }
+                Arg::Flag(flag) => flag,
+            };
+            match flag.as_str() {
+                "-s" | "--case-sensitive" => case_mode = Some(CaseMode::Sensitive),
+                "-i" | "--ignore-case" => case_mode = Some(CaseMode::Insensitive),
+                "-S" | "--smart-case" => case_mode = Some(CaseMode::Smart),
+                "-e" | "--regexp" => {
+                    patterns.push(parser.value(&flag)?);
+                    patterns_given = true;
+                }
+                "-f" | "--file" => {
+                    let path = parser.value(&flag)?;
+                    let contents = fs::read_to_string(&path)
+                        .map_err(|source| Error::PatternFile { path: PathBuf::from(&path), source })?;
+                    patterns.extend(contents.lines().map(String::from));
+                    patterns_given = true;
+                }
+                "-E" | "--regex" => regex = true,
+                "--fuzzy" => fuzzy = Some(parser.number(&flag)?),
+                "-U" | "--multiline" => multiline = true,
+                "--tui" => tui = true,
+                "-v" | "--invert-match" => invert_match = true,
+                "-w" | "--word-regexp" => word = true,
+                "-x" | "--line-regexp" => whole_line = true,
+                "-n" | "--line-number" => line_numbers = true,
+                "-N" | "--no-line-number" => line_numbers = false,
+                "-A" | "--after-context" => after_context = Some(parser.number(&flag)?),
+                "-B" | "--before-context" => before_context = Some(parser.number(&flag)?),
+                "-C" | "--context" => context = Some(parser.number(&flag)?),
+                "--color" | "--colour" => {
+                    //the value is optional, so it's only taken from --color=WHEN and never from the next argument
+                    color = match parser.optional_value() {
+                        None => ColorChoice::Auto,
+                        Some(value) => ColorChoice::parse(&value).ok_or_else(|| {
+                            let expected = "auto, always or never";
+                            ArgsError::InvalidValue { option: flag.clone(), value: value.clone(), expected }
+                        })?,
+                    }
+                }
+                "-j" | "--threads" => threads = parser.number(&flag)?,
+                "--include" => include.push(parser.value(&flag)?),
+                "--exclude" => exclude.push(parser.value(&flag)?),
+                "--hidden" => hidden = true,
+                "--no-hidden" => hidden = false,
+                "--no-ignore" => no_ignore = true,
+                "--ignore" => no_ignore = false,
+                "-a" | "--text" => text = true,
+                "--no-mmap" => mmap = false,
+                "-z" | "--search-zip" => search_zip = true,
+                "--no-search-zip" => search_zip = false,
+                "--json" => output_mode = OutputMode::Json,
+                "-m" | "--max-count" => max_count = Some(parser.number(&flag)?),
+                "--replace" => replace = Some(parser.value(&flag)?),
+                "--in-place" => in_place = true,
+                "--apply" => apply = true,
+                "-c" | "--count" => output_mode = OutputMode::Count,
+                "-l" | "--files-with-matches" => output_mode = OutputMode::FilesWithMatches,
+                "-L" | "--files-without-match" => output_mode = OutputMode::FilesWithoutMatch,
+                "-q" | "--quiet" => quiet = true,
+                "--watch" => watch = true,
+                "--stats" => stats = true,
+                "-h" | "--help" => return Err(ArgsError::Help.into()),
+                "-V" | "--version" => return Err(ArgsError::Version.into()),
+                _ => return Err(ArgsError::UnknownOption(flag).into()),
+            }
+            parser.reject_unused_value(&flag)?;
+        }
+
+        //--include on the command line asks for just those files, so it replaces the configured
+        //globs; --exclude adds to them, there's --no-ignore for seeing everything
+        if include.is_empty() {
+            include = defaults.include;
+        }
+
+        //like grep, -x wins over -w when both are given
+        let boundary = if whole_line {
+            Boundary::Line
+        } else if word {
+            Boundary::Word
+        } else {
+            Boundary::Anywhere
+        };
+
+        //-q silences everything, wherever it appears among the other output flags
+        if quiet {
+            output_mode = OutputMode::Quiet;
+        }
+
+        let mut positional = positional.into_iter();
+        if !patterns_given {
+            let query = positional.next().ok_or(ArgsError::MissingQuery)?;
+            patterns.push(query);
+        }
+        let mut file_paths: Vec<String> = positional.collect();
+        if file_paths.is_empty() {
+            file_paths.push(String::from("-")); //nothing to search means read from a pipe, like grep
+        }
+
+        let requires = |option: &str, requirement| Error::from(ArgsError::Requires { option: option.to_string(), requirement });
+        if let Some(k) = fuzzy {
+            if regex || patterns.len() != 1 {
+                return Err(requires("--fuzzy", "a single literal query, without -E"));
+            }
+            //with as many edits as the query is long, anything at all would match
+            if k >= patterns[0].chars().count() {
+                let expected = "fewer edits than the query has characters";
+                return Err(ArgsError::InvalidValue { option: String::from("--fuzzy"), value: k.to_string(), expected }.into());
+            }
+        }
+        //-v and --in-place work a line at a time, and so does the fuzzy search
+        if multiline && (invert_match || fuzzy.is_some() || in_place) {
+            return Err(requires("--multiline", "a search without -v, --fuzzy or --in-place"));
+        }
+        //the browser shows the hits of one query it can edit, instead of any of the other outputs
+        if tui && patterns.len() != 1 {
+            return Err(requires("--tui", "a single QUERY"));
+        }
+        if tui && (in_place || output_mode != OutputMode::Lines) {
+            return Err(requires("--tui", "a search without --in-place, --json, -c, -l, -L or -q"));
+        }
+        //the files are followed for what's appended to them, which only a hit by hit output can show
+        if watch && !cfg!(target_os = "linux") {
+            return Err(requires("--watch", "Linux, where inotify tells it about changes"));
+        }
+        if watch && file_paths.iter().any(|path| path == "-") {
+            return Err(requires("--watch", "files or directories to follow, not standard input"));
+        }
+        if watch && (multiline || tui || in_place || !matches!(output_mode, OutputMode::Lines | OutputMode::Json)) {
+            return Err(requires("--watch", "a search without -U, --tui, --in-place, -c, -l, -L or -q"));
+        }
+        //the numbers are printed once the run is over, and those two aren't over before they're stopped
+        if stats && (tui || watch) {
+            return Err(requires("--stats", "a search without --tui or --watch"));
+        }
+        if in_place && replace.is_none() {
+            return Err(requires("--in-place", "--replace TEXT"));
+        }
+        if apply && !in_place {
+            return Err(requires("--apply", "--in-place"));
+        }
+        //the lines -v selects are the ones without a match, so there'd be nothing to replace in them
+        if in_place && invert_match {
+            return Err(requires("--in-place", "a search without -v"));
+        }
+        //there's nothing to write the result back into
+        if in_place && file_paths.iter().any(|path| path == "-") {
+            return Err(requires("--in-place", "files to rewrite, not standard input"));
+        }
+
+        //wrap the values in a Config struct in the Ok variant
+        Ok(Config {
+            patterns,
+            file_paths,
+            case_mode,
+            default_case: defaults.case_mode,
+            regex,
+            invert_match,
+            boundary,
+            line_numbers,
+            before_context: before_context.or(context).unwrap_or(0),
+            after_context: after_context.or(context).unwrap_or(0),
+            output_mode,
+            color,
+            threads,
+            include,
+            exclude,
+            hidden,
+            no_ignore,
+            text,
+            mmap,
+            search_zip,
+            max_count,
+            fuzzy,
+            multiline,
+            tui,
+            watch,
+            stats,
+            replace,
+            in_place,
+            apply,
+        })
+    }
+
+    //a flag wins over the defaults, so -s is a sensitive search even with IGNORE_CASE set
+    pub fn is_case_insensitive(&self) -> bool {
+        self.ignores_case_of(&self.patterns)
+    }
+
+    //the same for other patterns than the ones on the command line: --tui asks on every keystroke,
+    //since -S depends on what has been typed so far
+    pub fn ignores_case_of(&self, patterns: &[String]) -> bool {
+        match self.case_mode.or(self.default_case) {
+            Some(CaseMode::Insensitive) => true,
+            Some(CaseMode::Smart) => !patterns.iter().any(|pattern| casefold::has_uppercase(pattern, self.regex)),
+            Some(CaseMode::Sensitive) | None => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Arg {
+    Flag(String), //"-x" for short flags, "--name" for long ones
+    Positional(String),
+}
+
+//splits the raw arguments into flags and positional values:
+//  -in        is the same as -i -n
+//  -A3, -A 3  give the value 3 to -A
+//  --context=3 and --context 3 give the value 3 to --context
+//  --         ends the flags, everything after it is positional (so is a lone "-")
+struct ArgParser<'a> {
+    args: &'a [String],
+    next: usize,
+    cluster: Vec<char>, //short flags still waiting in the current "-abc" argument
+    long_value: Option<String>, //the part after '=' in the current --long=value argument
+    only_positional: bool,
+}
+
+impl<'a> ArgParser<'a> {
+    fn new(args: &'a [String]) -> ArgParser<'a> {
+        ArgParser { args, next: 0, cluster: Vec::new(), long_value: None, only_positional: false }
+    }
+
+    fn next(&mut self) -> Result<Option<Arg>, ArgsError> {
+        if !self.cluster.is_empty() {
+            let c = self.cluster.remove(0);
+            return Ok(Some(Arg::Flag(format!("-{c}"))));
+        }
+
+        let arg = match self.args.get(self.next) {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+        self.next += 1;
+
+        if self.only_positional || arg == "-" || !arg.starts_with('-') {
+            return Ok(Some(Arg::Positional(arg.clone())));
+        }
+        if arg == "--" {
+            self.only_positional = true;
+            return self.next();
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            return Ok(Some(match long.split_once('=') {
+                Some((name, value)) => {
+                    self.long_value = Some(value.to_string());
+                    Arg::Flag(format!("--{name}"))
+                }
+                None => Arg::Flag(arg.clone()),
+            }));
+        }
+        self.cluster = arg.chars().skip(1).collect();
+        self.next()
+    }
+
+    //the value of the flag that was just returned by next()
+    fn value(&mut self, flag: &str) -> Result<String, ArgsError> {
+        if let Some(value) = self.long_value.take() {
+            return Ok(value);
+        }
+        if !self.cluster.is_empty() {
+            return Ok(self.cluster.drain(..).collect());
+        }
+        match self.args.get(self.next) {
+            Some(value) => {
+                self.next += 1;
+                Ok(value.clone())
+            }
+            None => Err(ArgsError::MissingValue(flag.to_string())),
+        }
+    }
+
+    //for flags whose value may be left out: only --flag=value counts
+    fn optional_value(&mut self) -> Option<String> {
+        self.long_value.take()
+    }
+
+    fn number(&mut self, flag: &str) -> Result<usize, ArgsError> {
+        let value = self.value(flag)?;
+        value
+            .parse()
+            .map_err(|_| ArgsError::InvalidValue { option: flag.to_string(), value, expected: "a number" })
+    }
+
+    //catches things like --regex=yes, where a flag that takes no value was given one
+    fn reject_unused_value(&mut self, flag: &str) -> Result<(), ArgsError> {
+        match self.long_value.take() {
+            Some(_) => Err(ArgsError::UnexpectedValue(flag.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn build(args: &[&str]) -> Result<Config, Error> {
+        let args: Vec<String> = ["minigrep"].iter().chain(args).map(|s| s.to_string()).collect();
+        //the tests don't see the config file or the environment of whoever runs them
+        Config::build_with(&args, Defaults::default())
+    }
+
+    fn bad(args: &[&str]) -> ArgsError {
+        match build(args) {
+            Err(Error::Args(e)) => e,
+            other => panic!("expected a bad argument, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn flags_can_go_anywhere() {
+        let config = build(&["to", "poem.txt", "-i", "src"]).unwrap();
+        assert_eq!(vec!["to"], config.patterns);
+        assert_eq!(vec!["poem.txt", "src"], config.file_paths);
+        assert_eq!(Some(CaseMode::Insensitive), config.case_mode);
+
+        let config = build(&["-nE", "--context=2", "-B1", "to", "poem.txt", "-j4"]).unwrap();
+        assert!(config.line_numbers && config.regex);
+        assert_eq!(4, config.threads);
+        assert_eq!(Some(2), build(&["-m2", "to"]).unwrap().max_count);
+        let replacing = build(&["-E", "(b)og", "--in-place", "poem.txt", "--replace", "${1}ug", "--apply"]).unwrap();
+        assert_eq!((Some("${1}ug"), true, true), (replacing.replace.as_deref(), replacing.in_place, replacing.apply));
+        assert_eq!((1, 2), (config.before_context, config.after_context));
+        for args in [["-A", "1", "-C", "3", "to"], ["-C", "3", "-A", "1", "to"]] {
+            let config = build(&args).unwrap();
+            assert_eq!((3, 1), (config.before_context, config.after_context), "{args:?}");
+        }
+
+        let config = build(&["--include=*.rs", "fn", ".", "--exclude", "target/**", "--include", "*.toml", "-a"]).unwrap();
+        assert_eq!(vec!["*.rs", "*.toml"], config.include);
+        assert_eq!(vec!["target/**"], config.exclude);
+        assert!(config.text && !config.hidden && !config.no_ignore && config.mmap);
+        assert!(!build(&["--no-mmap", "to"]).unwrap().mmap);
+        assert!(!config.search_zip && build(&["-z", "to"]).unwrap().search_zip);
+
+        assert_eq!(OutputMode::Quiet, build(&["-q", "-c", "to"]).unwrap().output_mode);
+
+        let config = build(&["--color", "to", "poem.txt"]).unwrap();
+        assert_eq!((ColorChoice::Auto, vec![String::from("poem.txt")]), (config.color, config.file_paths));
+        assert_eq!(ColorChoice::Never, build(&["--colour=never", "to"]).unwrap().color);
+    }
+
+    #[test]
+    fn smart_case() {
+        let insensitive = |args: &[&str]| {
+            build(args).unwrap().is_case_insensitive()
+        };
+        assert!(insensitive(&["-S", "to"]));
+        assert!(!insensitive(&["-S", "To"]));
+        assert!(insensitive(&["-SE", r"\w+o\b"]));
+        assert!(!insensitive(&["-S", "-e", "to", "-e", "Bog"]));
+        assert!(!insensitive(&["-S", "-s", "to"]));
+    }
+
+    #[test]
+    fn flags_override_the_defaults() {
+        let defaults = Defaults {
+            color: Some(ColorChoice::Always),
+            case_mode: Some(CaseMode::Insensitive),
+            line_numbers: Some(true),
+            hidden: Some(true),
+            search_zip: Some(true),
+            include: vec![String::from("*.rs")],
+            exclude: vec![String::from("target/**")],
+            ..Defaults::default()
+        };
+        let build = |args: &[&str]| {
+            let args: Vec<String> = ["minigrep"].iter().chain(args).map(|s| s.to_string()).collect();
+            Config::build_with(&args, defaults.clone()).unwrap()
+        };
+        let config = build(&["To", "src"]);
+        assert!(config.is_case_insensitive() && config.line_numbers && config.hidden && config.search_zip);
+        assert_eq!(ColorChoice::Always, config.color);
+        assert_eq!(vec!["*.rs"], config.include);
+
+        //IGNORE_CASE used to win over -s
+        let config = build(&["-s", "-N", "--no-hidden", "--no-search-zip", "--color=never", "--include=*.toml", "--exclude", "*.lock", "To"]);
+        assert!(!config.is_case_insensitive() && !config.line_numbers && !config.hidden && !config.search_zip);
+        assert_eq!(ColorChoice::Never, config.color);
+        assert_eq!(vec!["*.toml"], config.include);
+        assert_eq!(vec!["target/**", "*.lock"], config.exclude);
+    }
+
+    #[test]
+    fn patterns_from_flags_and_files() {
+        let file = std::env::temp_dir().join(format!("minigrep-patterns-{}", std::process::id()));
+        fs::write(&file, "bog\nfrog\n").unwrap();
+        let config = build(&["-e", "to", "-f", &file.display().to_string(), "poem.txt"]).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(vec!["to", "bog", "frog"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths); //no positional QUERY once -e is used
+    }
+
+    #[test]
+    fn double_dash_ends_the_flags() {
+        let config = build(&["-s", "--", "-n", "-"]).unwrap();
+        assert_eq!(vec!["-n"], config.patterns);
+        assert_eq!(vec!["-"], config.file_paths);
+        assert!(!config.line_numbers);
+
+        assert_eq!(vec!["-"], build(&["to"]).unwrap().file_paths);
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert_eq!(ArgsError::UnknownOption(String::from("-Z")), bad(&["-Z", "to", "poem.txt"]));
+        assert_eq!(ArgsError::MissingValue(String::from("-A")), bad(&["to", "poem.txt", "-A"]));
+        assert_eq!(ArgsError::UnexpectedValue(String::from("--regex")), bad(&["--regex=yes", "to", "poem.txt"]));
+        let invalid = bad(&["-C", "two", "to"]);
+        assert_eq!("option '-C' needs a number, got 'two'", invalid.to_string());
+        assert_eq!(ArgsError::MissingQuery, bad(&[]));
+        match build(&["-f", "missing-patterns.txt", "poem.txt"]) {
+            Err(Error::PatternFile { path, source }) => {
+                assert_eq!((PathBuf::from("missing-patterns.txt"), io::ErrorKind::NotFound), (path, source.kind()))
+            }
+            other => panic!("expected the pattern file to be missing, got {other:?}"),
+        }
+        let requires = |option: &str, requirement| ArgsError::Requires { option: option.to_string(), requirement };
+        assert_eq!(requires("--in-place", "--replace TEXT"), bad(&["--in-place", "to", "poem.txt"]));
+        assert_eq!(requires("--apply", "--in-place"), bad(&["--replace", "so", "--apply", "to", "poem.txt"]));
+        assert_eq!(requires("--in-place", "a search without -v"), bad(&["--replace", "so", "--in-place", "-v", "to", "poem.txt"]));
+        assert_eq!("option '--in-place' needs files to rewrite, not standard input", bad(&["--replace", "so", "--in-place", "to"]).to_string());
+        //no -r: grep users would take it for a recursive search and have the query read as TEXT
+        assert_eq!(ArgsError::UnknownOption(String::from("-r")), bad(&["-r", "foo", "src"]));
+        assert_eq!(requires("--fuzzy", "a single literal query, without -E"), bad(&["--fuzzy", "1", "-E", "n.body"]));
+        assert_eq!("option '--fuzzy' needs fewer edits than the query has characters, got '2'", bad(&["--fuzzy=2", "to"]).to_string());
+        assert_eq!(Some(1), build(&["--fuzzy", "1", "nobody"]).unwrap().fuzzy);
+        assert_eq!(requires("--multiline", "a search without -v, --fuzzy or --in-place"), bad(&["-Uv", "fn"]));
+        assert!(build(&["--multiline", "-E", "fn\\s+main"]).unwrap().multiline);
+        assert_eq!(requires("--tui", "a single QUERY"), bad(&["--tui", "-e", "fn", "-e", "struct"]));
+        assert_eq!(requires("--tui", "a search without --in-place, --json, -c, -l, -L or -q"), bad(&["--tui", "-c", "fn"]));
+        assert!(build(&["--tui", "fn", "src"]).unwrap().tui);
+        assert_eq!(requires("--watch", "files or directories to follow, not standard input"), bad(&["--watch", "ERROR"]));
+        assert_eq!(requires("--watch", "a search without -U, --tui, --in-place, -c, -l, -L or -q"), bad(&["--watch", "-c", "ERROR", "app.log"]));
+        assert!(build(&["--watch", "--json", "ERROR", "app.log"]).unwrap().watch);
+        assert_eq!(requires("--stats", "a search without --tui or --watch"), bad(&["--stats", "--tui", "fn"]));
+        assert!(build(&["--stats", "-c", "fn", "src"]).unwrap().stats);
+        assert_eq!(ArgsError::Help, bad(&["to", "--help"]));
+        assert_eq!(ArgsError::Version, bad(&["-V"]));
+    }
+}
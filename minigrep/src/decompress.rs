@@ -0,0 +1,99 @@
+use std::io::{self, BufRead, BufReader};
+
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use liblzma::bufread::XzDecoder;
+
+// -z: rotated logs end up as log.1.gz, log.2.xz and so on, and with -z they're searched as if they
+// had never been compressed. The format is told by the magic bytes the file starts with, not by its
+// name, so a compressed file without the usual extension is still found, and a plain file is read
+// as it is. The text is decompressed as it's read, a chunk at a time, so a compressed log is never
+// unpacked whole, in memory or on disk; the line numbers and byte offsets are the ones in the
+// decompressed text.
+// Each format is read to its end even when the file is several compressed streams one after the
+// other, which is what `cat a.gz b.gz > c.gz` and the parallel compressors (pigz, pbzip2) produce.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Gzip,
+  Bzip2,
+  Xz,
+  Zstd,
+}
+
+impl Format {
+  //the format of input starting with these bytes, if it's one of the compressed ones
+  pub fn detect(start: &[u8]) -> Option<Format> {
+    match start {
+      [0x1F, 0x8B, ..] => Some(Format::Gzip),
+      //"BZh" and the block size, '1' to '9'
+      [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Format::Bzip2),
+      [0xFD, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Format::Xz),
+      [0x28, 0xB5, 0x2F, 0xFD, ..] => Some(Format::Zstd),
+      _ => None,
+    }
+  }
+}
+
+//the input decompressed if it starts like one of the formats, or given back as it is if it doesn't
+pub fn decompress<'a>(mut reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn BufRead + 'a>> {
+  let Some(format) = Format::detect(reader.fill_buf()?) else {
+    return Ok(reader);
+  };
+  Ok(match format {
+    Format::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+    Format::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+    Format::Xz => Box::new(BufReader::new(XzDecoder::new_multi_decoder(reader))),
+    Format::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Cursor, Read, Write};
+
+  fn read_all(bytes: Vec<u8>) -> io::Result<String> {
+    let mut text = String::new();
+    //small chunks, so the magic bytes and the compressed streams get split between them
+    decompress(Box::new(BufReader::with_capacity(7, Cursor::new(bytes))))?.read_to_string(&mut text)?;
+    Ok(text)
+  }
+
+  #[test]
+  fn every_format_by_its_magic_bytes() {
+    let poem = include_str!("../poem.txt");
+    let gzip = |text: &str| {
+      let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+      encoder.write_all(text.as_bytes()).unwrap();
+      encoder.finish().unwrap()
+    };
+    let bzip2 = {
+      let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+      encoder.write_all(poem.as_bytes()).unwrap();
+      encoder.finish().unwrap()
+    };
+    let xz = {
+      let mut encoder = liblzma::write::XzEncoder::new(Vec::new(), 6);
+      encoder.write_all(poem.as_bytes()).unwrap();
+      encoder.finish().unwrap()
+    };
+    let zstd = zstd::encode_all(poem.as_bytes(), 0).unwrap();
+
+    assert_eq!(Some(Format::Gzip), Format::detect(&gzip(poem)));
+    assert_eq!(Some(Format::Bzip2), Format::detect(&bzip2));
+    assert_eq!(Some(Format::Xz), Format::detect(&xz));
+    assert_eq!(Some(Format::Zstd), Format::detect(&zstd));
+    for compressed in [gzip(poem), bzip2, xz, zstd] {
+      assert_eq!(poem, read_all(compressed).unwrap());
+    }
+    //two gzip files one after the other are one gzip file
+    assert_eq!("first\nsecond\n", read_all([gzip("first\n"), gzip("second\n")].concat()).unwrap());
+    //plain text, including text that starts like a format but isn't one
+    assert_eq!(poem, read_all(poem.as_bytes().to_vec()).unwrap());
+    assert_eq!("BZh is not bzip2", read_all(b"BZh is not bzip2".to_vec()).unwrap());
+    //a file cut short is an error, not a search that quietly stops halfway
+    let truncated = gzip(&poem.repeat(50));
+    assert!(read_all(truncated[..truncated.len() / 2].to_vec()).is_err());
+  }
+}
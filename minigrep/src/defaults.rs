@@ -0,0 +1,328 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{CaseMode, ColorChoice};
+use crate::error::Error;
+
+// Defaults for some of the options, so they don't have to be typed every time.
+// They come in layers, each one overriding what the one before it set:
+//   1. the config file, ~/.config/minigrep/config.toml ($XDG_CONFIG_HOME/minigrep/config.toml when
+//      that is set, or whatever file MINIGREP_CONFIG names; an empty MINIGREP_CONFIG means none)
+//   2. the environment: MINIGREP_COLOR, MINIGREP_CASE, ... (the setting's name in capitals, with '_'
+//      for '-'), and the book's IGNORE_CASE, which is the same as MINIGREP_CASE=insensitive
+//   3. the command line, see Config::build_with()
+// The file is a small part of TOML: `key = value` lines, with strings, booleans, numbers and
+// one-line arrays of strings, and '#' comments. In the environment everything is a string,
+// booleans are true/false (or 1/0, yes/no) and lists are separated by commas.
+
+//one value of a setting, whichever layer it came from
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+  Str(String),
+  Bool(bool),
+  Int(usize),
+  List(Vec<String>),
+}
+
+//for the messages: how the value would be written in the file
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Str(s) => write!(f, "'{s}'"),
+      Value::Bool(b) => write!(f, "{b}"),
+      Value::Int(n) => write!(f, "{n}"),
+      Value::List(_) => write!(f, "an array"),
+    }
+  }
+}
+
+impl Value {
+  fn string(self) -> Result<String, String> {
+    match self {
+      Value::Str(s) => Ok(s),
+      other => Err(format!("expected a string, got {other}")),
+    }
+  }
+
+  fn boolean(self) -> Result<bool, String> {
+    match self {
+      Value::Bool(b) => Ok(b),
+      Value::Str(s) => match s.as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(format!("expected true or false, got '{s}'")),
+      },
+      other => Err(format!("expected true or false, got {other}")),
+    }
+  }
+
+  fn number(self) -> Result<usize, String> {
+    match self {
+      Value::Int(n) => Ok(n),
+      Value::Str(s) => s.parse().map_err(|_| format!("expected a number, got '{s}'")),
+      other => Err(format!("expected a number, got {other}")),
+    }
+  }
+
+  fn list(self) -> Result<Vec<String>, String> {
+    match self {
+      Value::List(items) => Ok(items),
+      Value::Str(s) => Ok(s.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect()),
+      other => Err(format!("expected a list of strings, got {other}")),
+    }
+  }
+}
+
+//what the config file and the environment set; None (or an empty list) leaves the built-in default
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Defaults {
+  pub color: Option<ColorChoice>,
+  pub case_mode: Option<CaseMode>,
+  pub threads: Option<usize>,
+  pub line_numbers: Option<bool>,
+  pub hidden: Option<bool>,
+  pub no_ignore: Option<bool>,
+  pub search_zip: Option<bool>,
+  pub include: Vec<String>,
+  pub exclude: Vec<String>,
+}
+
+impl Defaults {
+  //the config file, then the environment of this process
+  pub fn load() -> Result<Defaults, Error> {
+    //a variable that isn't valid UTF-8 can't be one of ours
+    let vars: Vec<(String, String)> = env::vars_os().filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?))).collect();
+    let file = match config_path(&vars) {
+      Some(path) => match fs::read_to_string(&path) {
+        Ok(contents) => Some((path, contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(Error::Settings { origin: path.display().to_string(), message: e.to_string() }),
+      },
+      None => None,
+    };
+    Defaults::from_layers(file.as_ref().map(|(path, contents)| (path.as_path(), contents.as_str())), &vars)
+  }
+
+  //the contents of a config file (with its path, for the messages) overridden by environment variables
+  pub fn from_layers(file: Option<(&Path, &str)>, vars: &[(String, String)]) -> Result<Defaults, Error> {
+    let mut defaults = Defaults::default();
+    if let Some((path, contents)) = file {
+      for (number, line) in contents.lines().enumerate() {
+        let origin = || format!("{}:{}", path.display(), number + 1);
+        let setting = parse_line(line).map_err(|message| Error::Settings { origin: origin(), message })?;
+        if let Some((key, value)) = setting {
+          if !defaults.set(&key, value).map_err(|message| Error::Settings { origin: origin(), message })? {
+            return Err(Error::Settings { origin: origin(), message: format!("unknown setting '{key}'") });
+          }
+        }
+      }
+    }
+
+    //like it always has, IGNORE_CASE counts when it's set at all, whatever its value
+    if vars.iter().any(|(key, _)| key == "IGNORE_CASE") {
+      defaults.case_mode = Some(CaseMode::Insensitive);
+    }
+    for (key, value) in vars {
+      let Some(name) = key.strip_prefix("MINIGREP_") else { continue };
+      if name == "CONFIG" {
+        continue;
+      }
+      let name = name.to_lowercase().replace('_', "-");
+      //unlike a line in the config file, a variable can be meant for another version of minigrep or
+      //be set for some other reason, so one that isn't a setting only gets a warning
+      if !defaults.set(&name, Value::Str(value.clone())).map_err(|message| Error::Settings { origin: key.clone(), message })? {
+        eprintln!("minigrep: warning: {key}: unknown setting, ignored");
+      }
+    }
+    Ok(defaults)
+  }
+
+  //false if there's no setting called `key`; Err if there is, but the value doesn't suit it
+  fn set(&mut self, key: &str, value: Value) -> Result<bool, String> {
+    match key {
+      "color" | "colour" => {
+        let value = value.string()?;
+        self.color = Some(ColorChoice::parse(&value).ok_or(format!("expected auto, always or never, got '{value}'"))?);
+      }
+      "case" => {
+        let value = value.string()?;
+        self.case_mode = Some(CaseMode::parse(&value).ok_or(format!("expected sensitive, insensitive or smart, got '{value}'"))?);
+      }
+      "threads" => self.threads = Some(value.number()?),
+      "line-number" => self.line_numbers = Some(value.boolean()?),
+      "hidden" => self.hidden = Some(value.boolean()?),
+      "no-ignore" => self.no_ignore = Some(value.boolean()?),
+      "search-zip" => self.search_zip = Some(value.boolean()?),
+      "include" => self.include = value.list()?,
+      "exclude" => self.exclude = value.list()?,
+      _ => return Ok(false),
+    }
+    Ok(true)
+  }
+}
+
+//MINIGREP_CONFIG, or config.toml in the XDG config directory
+fn config_path(vars: &[(String, String)]) -> Option<PathBuf> {
+  let var = |name: &str| vars.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+  if let Some(path) = var("MINIGREP_CONFIG") {
+    return (!path.is_empty()).then(|| PathBuf::from(path));
+  }
+  let dir = match var("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+    Some(dir) => PathBuf::from(dir),
+    None => PathBuf::from(var("HOME")?).join(".config"),
+  };
+  Some(dir.join("minigrep").join("config.toml"))
+}
+
+//one line of the config file: None for blank lines and comments
+fn parse_line(line: &str) -> Result<Option<(String, Value)>, String> {
+  let line = line.trim();
+  if line.is_empty() || line.starts_with('#') {
+    return Ok(None);
+  }
+  if line.starts_with('[') {
+    return Err(String::from("tables aren't supported, every setting goes at the top level"));
+  }
+  let (key, value) = line.split_once('=').ok_or_else(|| format!("expected `key = value`, got '{line}'"))?;
+  let key = key.trim();
+  if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+    return Err(format!("'{key}' isn't a valid key"));
+  }
+  let mut chars = value.trim().chars().peekable();
+  let value = parse_value(&mut chars)?;
+  //after the value there may only be a comment
+  let rest: String = chars.collect();
+  if !rest.trim().is_empty() && !rest.trim().starts_with('#') {
+    return Err(format!("unexpected '{}' after the value", rest.trim()));
+  }
+  Ok(Some((key.replace('_', "-"), value)))
+}
+
+fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Value, String> {
+  match chars.peek() {
+    Some('"' | '\'') => parse_string(chars).map(Value::Str),
+    Some('[') => {
+      chars.next();
+      let mut items = Vec::new();
+      loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+          Some(']') => {
+            chars.next();
+            return Ok(Value::List(items));
+          }
+          Some('"' | '\'') => items.push(parse_string(chars)?),
+          _ => return Err(String::from("arrays hold strings and end with ']' on the same line")),
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        //a comma after the last item is allowed, like in TOML
+        if chars.next_if_eq(&',').is_none() && chars.peek() != Some(&']') {
+          return Err(String::from("expected ',' or ']' in the array"));
+        }
+      }
+    }
+    _ => {
+      let word: String = std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_')).collect();
+      match word.as_str() {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        //TOML allows '_' between digits, as in 1_000
+        _ if word.starts_with(|c: char| c.is_ascii_digit()) => {
+          word.replace('_', "").parse().map(Value::Int).map_err(|_| format!("'{word}' isn't a number"))
+        }
+        _ => Err(format!("expected a string, a number, true, false or an array, got '{word}'")),
+      }
+    }
+  }
+}
+
+//a "basic" string, with backslash escapes, or a 'literal' one, without
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+  let quote = chars.next();
+  let mut s = String::new();
+  loop {
+    match (chars.next(), quote) {
+      (None, _) => return Err(String::from("unterminated string")),
+      (c, q) if c == q => return Ok(s),
+      (Some('\\'), Some('"')) => {
+        let escaped = match chars.next() {
+          Some('n') => '\n',
+          Some('t') => '\t',
+          Some('r') => '\r',
+          Some(c @ ('"' | '\\')) => c,
+          Some('u') => {
+            let hex: String = chars.by_ref().take(4).collect();
+            u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or(format!("invalid escape '\\u{hex}'"))?
+          }
+          other => return Err(format!("invalid escape '\\{}'", other.map(String::from).unwrap_or_default())),
+        };
+        s.push(escaped);
+      }
+      (Some(c), _) => s.push(c),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+  }
+
+  #[test]
+  fn file_then_environment() {
+    let file = r#"
+      # team defaults
+      color = "always"
+      case = 'smart'   # unless the query has capitals
+      threads = 1_2
+      line-number = true
+      exclude = ["target/**", "*.min.js",]
+      include = [ "src/*.rs" ]
+    "#;
+    let defaults = Defaults::from_layers(Some((Path::new("config.toml"), file)), &[]).unwrap();
+    assert_eq!(Some(ColorChoice::Always), defaults.color);
+    assert_eq!((Some(CaseMode::Smart), Some(12), Some(true)), (defaults.case_mode, defaults.threads, defaults.line_numbers));
+    assert_eq!(vec!["target/**", "*.min.js"], defaults.exclude);
+    assert_eq!(vec!["src/*.rs"], defaults.include);
+
+    let env = vars(&[("IGNORE_CASE", ""), ("MINIGREP_COLOR", "never"), ("MINIGREP_EXCLUDE", "a, b"), ("MINIGREP_NO_IGNORE", "1"), ("MINIGREP_SEARCH_ZIP", "yes"), ("PATH", "/bin")]);
+    let defaults = Defaults::from_layers(Some((Path::new("config.toml"), file)), &env).unwrap();
+    assert_eq!((Some(ColorChoice::Never), Some(CaseMode::Insensitive)), (defaults.color, defaults.case_mode));
+    assert_eq!(vec!["a", "b"], defaults.exclude);
+    assert_eq!((Some(true), Some(true)), (defaults.no_ignore, defaults.search_zip));
+    //MINIGREP_CASE is more specific than IGNORE_CASE, wherever it comes in the environment
+    let env = vars(&[("MINIGREP_CASE", "sensitive"), ("IGNORE_CASE", "1")]);
+    assert_eq!(Some(CaseMode::Sensitive), Defaults::from_layers(None, &env).unwrap().case_mode);
+    //a variable that isn't a setting is skipped, the ones around it still count
+    let env = vars(&[("MINIGREP_THREADS", "3"), ("MINIGREP_COLOUR_SCHEME", "dark"), ("MINIGREP_HIDDEN", "1")]);
+    let defaults = Defaults::from_layers(None, &env).unwrap();
+    assert_eq!((Some(3), Some(true)), (defaults.threads, defaults.hidden));
+  }
+
+  #[test]
+  fn mistakes_say_where_they_are() {
+    let message = |file: &str, env: &[(&str, &str)]| Defaults::from_layers(Some((Path::new("config.toml"), file)), &vars(env)).unwrap_err().to_string();
+    assert_eq!("config.toml:2: unknown setting 'colour-scheme'", message("hidden = true\ncolour-scheme = 1", &[]));
+    assert_eq!("config.toml:1: expected true or false, got 3", message("hidden = 3", &[]));
+    assert_eq!("config.toml:1: expected a string, got an array", message("color = ['always']", &[]));
+    assert_eq!("config.toml:1: unterminated string", message("color = \"always", &[]));
+    assert_eq!("config.toml:1: unexpected 'x' after the value", message("threads = 2 x", &[]));
+    assert_eq!("MINIGREP_THREADS: expected a number, got 'many'", message("", &[("MINIGREP_THREADS", "many")]));
+    assert_eq!("MINIGREP_CASE: expected sensitive, insensitive or smart, got 'upper'", message("", &[("MINIGREP_CASE", "upper")]));
+  }
+
+  #[test]
+  fn where_the_file_is() {
+    let path = |env: &[(&str, &str)]| config_path(&vars(env));
+    assert_eq!(Some(PathBuf::from("/home/noel/.config/minigrep/config.toml")), path(&[("HOME", "/home/noel")]));
+    assert_eq!(Some(PathBuf::from("/xdg/minigrep/config.toml")), path(&[("HOME", "/home/noel"), ("XDG_CONFIG_HOME", "/xdg")]));
+    assert_eq!(Some(PathBuf::from("team.toml")), path(&[("HOME", "/home/noel"), ("MINIGREP_CONFIG", "team.toml")]));
+    assert_eq!(None, path(&[("HOME", "/home/noel"), ("MINIGREP_CONFIG", "")]));
+  }
+}
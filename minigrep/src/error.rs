@@ -0,0 +1,121 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::ArgsError;
+use crate::regex::RegexError;
+
+//everything that can make Config::build() or run() fail; the variants keep what was being worked
+//on, so a caller can tell bad arguments from a bad pattern from a file that couldn't be read
+//without parsing the message
+#[derive(Debug)]
+pub enum Error {
+  Args(ArgsError), //the command line doesn't make sense (or asked for --help/--version)
+  //a bad line in the config file or a bad MINIGREP_* variable; `origin` is "path:line" or the variable
+  Settings { origin: String, message: String },
+  PatternFile { path: PathBuf, source: io::Error }, //the file given to -f couldn't be read
+  Pattern(RegexError), //the -E pattern doesn't parse
+  //a file or directory couldn't be opened or read; run() only fails with it when --watch can't
+  //watch a path, files and directories that can't be read are reported one by one and counted in
+  //Unreadable
+  Read { path: PathBuf, source: io::Error },
+  Write(io::Error), //the results couldn't be written, e.g. because the pipe was closed
+  Rewrite { path: PathBuf, source: io::Error }, //--in-place couldn't write the changed file back
+  Unreadable { failed: usize, total: usize }, //the search finished, but some files were skipped
+}
+
+//only what was being done: the error that made it fail is the source(), and chain() prints both
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::Args(_) => write!(f, "bad command line"),
+      Error::Settings { origin, message } => write!(f, "{origin}: {message}"),
+      Error::PatternFile { path, .. } => write!(f, "can't read patterns from '{}'", path.display()),
+      Error::Pattern(_) => write!(f, "can't compile the pattern"),
+      Error::Read { path, .. } => write!(f, "{}", path.display()),
+      Error::Write(_) => write!(f, "can't write the results"),
+      Error::Rewrite { path, .. } => write!(f, "can't rewrite '{}'", path.display()),
+      Error::Unreadable { failed, total } => write!(f, "{failed} of {total} files could not be read"),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      Error::Args(e) => Some(e),
+      Error::Settings { .. } => None,
+      Error::PatternFile { source, .. } => Some(source),
+      Error::Pattern(e) => Some(e),
+      Error::Read { source, .. } => Some(source),
+      Error::Write(e) => Some(e),
+      Error::Rewrite { source, .. } => Some(source),
+      Error::Unreadable { .. } => None,
+    }
+  }
+}
+
+//an error followed by the ones that caused it, "a: b: c", which is how minigrep prints its errors
+pub struct Chain<'e>(&'e dyn error::Error);
+
+impl fmt::Display for Chain<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)?;
+    let mut source = self.0.source();
+    while let Some(e) = source {
+      write!(f, ": {e}")?;
+      source = e.source();
+    }
+    Ok(())
+  }
+}
+
+impl From<ArgsError> for Error {
+  fn from(e: ArgsError) -> Error {
+    Error::Args(e)
+  }
+}
+
+impl From<RegexError> for Error {
+  fn from(e: RegexError) -> Error {
+    Error::Pattern(e)
+  }
+}
+
+impl Error {
+  pub fn chain(&self) -> Chain<'_> {
+    Chain(self)
+  }
+
+  //for the map_err() calls around file system operations
+  pub fn read(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+    let path = path.into();
+    move |source| Error::Read { path, source }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::error::Error as _;
+
+  #[test]
+  fn messages_and_sources() {
+    let missing = Error::Read { path: PathBuf::from("poem.txt"), source: io::Error::new(io::ErrorKind::NotFound, "gone") };
+    assert_eq!("poem.txt", missing.to_string());
+    assert_eq!("gone", missing.source().unwrap().to_string());
+    assert_eq!("poem.txt: gone", missing.chain().to_string());
+
+    let pattern: Error = crate::regex::Regex::build("(to", false).unwrap_err().into();
+    assert!(matches!(pattern, Error::Pattern(RegexError { position: 3, .. })));
+    assert!(pattern.source().unwrap().downcast_ref::<RegexError>().is_some_and(|e| e.position == 3));
+    assert_eq!("can't compile the pattern: invalid regex \"(to\" at position 3: unclosed group", pattern.chain().to_string());
+
+    let args: Error = ArgsError::UnknownOption(String::from("-Z")).into();
+    assert_eq!("unknown option '-Z'", args.source().unwrap().to_string());
+    let write = Error::Write(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+    assert_eq!("can't write the results: closed", write.chain().to_string());
+    assert!(Error::Unreadable { failed: 1, total: 2 }.source().is_none());
+  }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use crate::casefold::fold_char;
+
+// --fuzzy K: approximate matching, where a line matches if some part of it is within K edits
+// (characters inserted, deleted or replaced) of the query, so "nobdy" and "n0body" find "nobody".
+// The search is Myers' bit-parallel algorithm: one column of the edit distance table, as it moves
+// along the line, is kept as bits (+1/-1 steps between neighbouring cells) in 64-bit words, so each
+// character of the line costs a few word operations per 64 characters of the query instead of a
+// loop over the query. It finds where a match ends; where it starts is then worked out with the
+// plain table, run backwards over just that stretch of the line.
+// Everything works on chars, and -i compares the one-to-one case folding of the characters.
+
+const WORD: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Fuzzy {
+  query: Vec<char>,
+  max_distance: usize,
+  ignore_case: bool,
+  //for every character of the query, a bit set on the rows of the table (positions in the query)
+  //where it appears, in as many words as the query needs
+  peq: HashMap<char, Vec<u64>>,
+  words: usize,
+}
+
+impl Fuzzy {
+  pub fn new(query: &str, max_distance: usize, ignore_case: bool) -> Fuzzy {
+    let normalize = |c| if ignore_case { fold_char(c) } else { c };
+    let query: Vec<char> = query.chars().map(normalize).collect();
+    let words = query.len().div_ceil(WORD).max(1);
+    let mut peq: HashMap<char, Vec<u64>> = HashMap::new();
+    for (i, &c) in query.iter().enumerate() {
+      peq.entry(c).or_insert_with(|| vec![0; words])[i / WORD] |= 1 << (i % WORD);
+    }
+    Fuzzy { query, max_distance, ignore_case, peq, words }
+  }
+
+  pub fn max_distance(&self) -> usize {
+    self.max_distance
+  }
+
+  fn normalize(&self, c: char) -> char {
+    if self.ignore_case {
+      fold_char(c)
+    } else {
+      c
+    }
+  }
+
+  //the leftmost match at or after the byte offset `at`; when the distance keeps going down over the
+  //next characters the match is extended to the closest one ("nobody" for "nobdy" rather than "nob")
+  pub fn find_at(&self, line: &str, at: usize) -> Option<(usize, usize)> {
+    let m = self.query.len();
+    if m <= self.max_distance {
+      return Some((at, at)); //an empty stretch is already close enough
+    }
+    let chars: Vec<(usize, char)> = line[at..].char_indices().map(|(i, c)| (i + at, c)).collect();
+
+    //the bottom cell of the column is the distance between the query and the best stretch of the
+    //line ending at the current character; it starts as m, the cost of inserting the whole query
+    let mut positive = vec![!0u64; self.words]; //vertical +1 steps: every cell of column 0 is one more than the one above
+    let mut negative = vec![0u64; self.words];
+    let last_row = 1u64 << ((m - 1) % WORD);
+    let mut score = m;
+    let mut best: Option<(usize, usize)> = None; //(characters up to the end of the match, distance)
+    for (j, &(_, c)) in chars.iter().enumerate() {
+      let eq = self.peq.get(&self.normalize(c));
+      //the top row of the table is all zeros, a match can start anywhere: nothing comes in from above
+      let mut carry = 0;
+      for word in 0..self.words {
+        let mask = if word == self.words - 1 { last_row } else { 1 << (WORD - 1) };
+        carry = advance(&mut positive[word], &mut negative[word], eq.map_or(0, |eq| eq[word]), carry, mask);
+      }
+      score = score.wrapping_add_signed(carry as isize);
+      match best {
+        None if score <= self.max_distance => best = Some((j + 1, score)),
+        Some((_, distance)) if score < distance => best = Some((j + 1, score)),
+        Some(_) => break,
+        None => {}
+      }
+      if best.is_some_and(|(_, distance)| distance == 0) {
+        break;
+      }
+    }
+    let (end, distance) = best?;
+
+    //now the table the other way: the query backwards against the line backwards from `end`,
+    //looking for the start that gives the same distance and the stretch closest to the query's length
+    let mut column: Vec<usize> = (0..=m).collect();
+    let mut next = vec![0; m + 1];
+    let mut start = (column[m] == distance).then_some(end);
+    for back in 1..=end.min(m + self.max_distance) {
+      let c = self.normalize(chars[end - back].1);
+      next[0] = back;
+      for i in 1..=m {
+        let replace = column[i - 1] + usize::from(self.query[m - i] != c);
+        next[i] = replace.min(column[i] + 1).min(next[i - 1] + 1);
+      }
+      std::mem::swap(&mut column, &mut next);
+      if column[m] == distance && start.is_none_or(|start| (end - start).abs_diff(m) > back.abs_diff(m)) {
+        start = Some(end - back);
+      }
+    }
+    let start = start?;
+    let byte = |index: usize| chars.get(index).map_or(line.len(), |&(i, _)| i);
+    Some((byte(start), byte(end)))
+  }
+
+  //the edit distance between the query and all of `text`, for reporting how close a match was
+  pub fn distance(&self, text: &str) -> usize {
+    let mut column: Vec<usize> = (0..=self.query.len()).collect();
+    for (j, c) in text.chars().map(|c| self.normalize(c)).enumerate() {
+      let mut diagonal = column[0];
+      column[0] = j + 1;
+      for i in 1..=self.query.len() {
+        let above = column[i];
+        column[i] = (diagonal + usize::from(self.query[i - 1] != c)).min(above + 1).min(column[i - 1] + 1);
+        diagonal = above;
+      }
+    }
+    column[self.query.len()]
+  }
+}
+
+//moves one word of the column along by one character of the line. `carry` is the horizontal step
+//(-1, 0 or +1) coming into the word's first row from the word above it, and the return value is the
+//step at the row picked by `mask`: the last row of the word, or of the query in the last word.
+//(Hyyrö's formulation of Myers' algorithm, as used by edlib.)
+fn advance(positive: &mut u64, negative: &mut u64, eq: u64, carry: i32, mask: u64) -> i32 {
+  let carry_negative = u64::from(carry < 0);
+  let xv = eq | *negative;
+  let eq = eq | carry_negative;
+  let xh = ((eq & *positive).wrapping_add(*positive) ^ *positive) | eq;
+  let mut horizontal_positive = *negative | !(xh | *positive);
+  let mut horizontal_negative = *positive & xh;
+  let out = i32::from(horizontal_positive & mask != 0) - i32::from(horizontal_negative & mask != 0);
+  horizontal_positive = (horizontal_positive << 1) | u64::from(carry > 0);
+  horizontal_negative = (horizontal_negative << 1) | carry_negative;
+  *positive = horizontal_negative | !(xv | horizontal_positive);
+  *negative = horizontal_positive & xv;
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //the same search done the slow way: every stretch of the line, closest first, then leftmost
+  fn brute_force(query: &str, line: &str, k: usize) -> Option<usize> {
+    let fuzzy = Fuzzy::new(query, k, false);
+    let bounds: Vec<usize> = line.char_indices().map(|(i, _)| i).chain([line.len()]).collect();
+    let mut best = None;
+    for (a, &start) in bounds.iter().enumerate() {
+      for &end in &bounds[a..] {
+        let d = fuzzy.distance(&line[start..end]);
+        if d <= k && best.is_none_or(|best| d < best) {
+          best = Some(d);
+        }
+      }
+    }
+    best
+  }
+
+  #[test]
+  fn finds_near_misses_with_their_distance() {
+    let fuzzy = Fuzzy::new("nobody", 1, false);
+    let line = "I'm nobdy! Who are you?";
+    assert_eq!(Some((4, 9)), fuzzy.find_at(line, 0));
+    assert_eq!(1, fuzzy.distance(&line[4..9]));
+    assert_eq!(None, fuzzy.find_at("I'm nbdy!", 0));
+    assert_eq!(Some((4, 10)), Fuzzy::new("nobody", 0, false).find_at("I'm nobody", 0));
+    assert_eq!(Some((0, 8)), Fuzzy::new("straße", 1, true).find_at("STRAẞEN", 0));
+    assert_eq!(None, fuzzy.find_at(line, 5));
+  }
+
+  #[test]
+  fn agrees_with_the_slow_way_on_long_queries() {
+    //longer than a word, so the steps have to be carried from one word into the next
+    let query = "How dreary to be somebody! How public, like a frog To tell your name the livelong day";
+    let line = "x How dreery to be sombody! How public like a frog To tell your name teh livelong day x";
+    for k in [0, 3, 5, 8] {
+      let fuzzy = Fuzzy::new(query, k, false);
+      let found = fuzzy.find_at(line, 0).map(|(start, end)| fuzzy.distance(&line[start..end]));
+      assert_eq!(brute_force(query, line, k).is_some(), found.is_some(), "k = {k}");
+      assert!(found.is_none_or(|d| d <= k));
+    }
+    for (query, line) in [("frog", "a fog in the bog"), ("livelong", "the lifelong day"), ("day", "dya")] {
+      let fuzzy = Fuzzy::new(query, 1, false);
+      let found = fuzzy.find_at(line, 0).map(|(start, end)| fuzzy.distance(&line[start..end]));
+      assert_eq!(brute_force(query, line, 1).is_some(), found.is_some(), "{query} in {line}");
+    }
+  }
+}
@@ -0,0 +1,149 @@
+use std::path::Path;
+
+// Shell style globs, with the rules .gitignore files use for them:
+// `*` matches anything but a '/', `?` one character that isn't a '/', `[abc]`, `[a-z]` and
+// `[!abc]` a set of characters, `**` any number of whole directories, and `\` escapes the next
+// character. A glob without a '/' is matched against the file name alone, at any depth; one with
+// a '/' (other than a trailing one) against the whole path from the directory it belongs to.
+// A trailing '/' means the glob only matches directories.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+  pattern: Vec<char>,
+  anchored: bool, //matched against the whole relative path rather than just the name
+  dir_only: bool,
+}
+
+impl Glob {
+  //every pattern is valid: a '[' without its ']' just stands for itself, like in git
+  pub fn new(pattern: &str) -> Glob {
+    let (pattern, dir_only) = match pattern.strip_suffix('/') {
+      Some(rest) => (rest, true),
+      None => (pattern, false),
+    };
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    Glob { pattern: pattern.chars().collect(), anchored, dir_only }
+  }
+
+  //`path` is relative to the directory the glob belongs to, with '/' between the components
+  pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+    if self.dir_only && !is_dir {
+      return false;
+    }
+    let text: Vec<char> = match self.anchored {
+      true => path.chars().collect(),
+      false => path.rsplit('/').next().unwrap_or(path).chars().collect(),
+    };
+    glob_match(&self.pattern, &text)
+  }
+
+  //whether the glob matches everything inside the directory `path` ("target/**" for "target"),
+  //so a walk can skip the directory instead of testing each file in it
+  pub fn matches_everything_in(&self, path: &str) -> bool {
+    match self.pattern.strip_suffix(&['/', '*', '*']) {
+      Some(prefix) if self.anchored => glob_match(prefix, &path.chars().collect::<Vec<char>>()),
+      _ => false,
+    }
+  }
+}
+
+//`path` relative to `root`, with '/' between the components whatever the platform uses,
+//or None if it isn't inside `root`
+pub fn relative_path(root: &Path, path: &Path) -> Option<String> {
+  let relative = path.strip_prefix(root).ok()?;
+  let components: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+  Some(components.join("/"))
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+  match pattern {
+    [] => text.is_empty(),
+    ['*', '*', '/', rest @ ..] => {
+      //zero or more whole directories: try the rest here and after every '/'
+      glob_match(rest, text) || (0..text.len()).any(|i| text[i] == '/' && glob_match(rest, &text[i + 1..]))
+    }
+    ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+    ['*', rest @ ..] => {
+      //as much as we like, but a single * never crosses into another directory
+      let stop = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+      (0..=stop).any(|i| glob_match(rest, &text[i..]))
+    }
+    ['?', rest @ ..] => matches!(text, [c, ..] if *c != '/') && glob_match(rest, &text[1..]),
+    ['[', class @ ..] => match class_match(class, text.first().copied()) {
+      Some((hit, len)) => hit && glob_match(&class[len..], &text[1..]),
+      None => text.first() == Some(&'[') && glob_match(class, &text[1..]),
+    },
+    ['\\', c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+  }
+}
+
+//`class` is what follows a '['; returns whether `c` is in the set and how much of the pattern the
+//set took up, or None when there's no closing ']'
+fn class_match(class: &[char], c: Option<char>) -> Option<(bool, usize)> {
+  let negated = matches!(class.first(), Some('!' | '^'));
+  let mut i = usize::from(negated);
+  let mut found = false;
+  let mut first = true;
+  loop {
+    match class.get(i)? {
+      //a ']' straight after the '[' (or '[!') is a member, not the end of the set
+      ']' if !first => return Some((c.is_some_and(|c| c != '/' && found != negated), i + 1)),
+      &lo => {
+        let (lo, step) = match (lo, class.get(i + 1)) {
+          ('\\', Some(&escaped)) => (escaped, 2),
+          _ => (lo, 1),
+        };
+        i += step;
+        let hi = match (class.get(i), class.get(i + 1)) {
+          (Some('-'), Some(&hi)) if hi != ']' => {
+            i += 2;
+            hi
+          }
+          _ => lo,
+        };
+        found |= c.is_some_and(|c| lo <= c && c <= hi);
+      }
+    }
+    first = false;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_and_paths() {
+    let rs = Glob::new("*.rs");
+    assert!(rs.matches("src/main.rs", false));
+    assert!(!rs.matches("src/main.rs.orig", false));
+
+    let target = Glob::new("/target/**");
+    assert!(target.matches("target/debug/minigrep", false));
+    assert!(!target.matches("src/target/x", false));
+    assert!(target.matches_everything_in("target"));
+    assert!(!Glob::new("target").matches_everything_in("target"));
+
+    let deep = Glob::new("src/**/test?.rs");
+    assert!(deep.matches("src/test1.rs", false));
+    assert!(deep.matches("src/a/b/test2.rs", false));
+    assert!(!deep.matches("src/a/test10.rs", false));
+    assert!(!Glob::new("src/*.rs").matches("src/a/main.rs", false));
+  }
+
+  #[test]
+  fn classes_escapes_and_directories() {
+    let digits = Glob::new("log[0-9][!a-z]");
+    assert!(digits.matches("log1_", false));
+    assert!(!digits.matches("log1a", false));
+    assert!(Glob::new("[]x]").matches("]", false));
+    assert!(Glob::new("a[b").matches("a[b", false));
+    assert!(Glob::new(r"\*").matches("*", false) && !Glob::new(r"\*").matches("a", false));
+
+    let dirs = Glob::new("build/");
+    assert!(dirs.matches("app/build", true));
+    assert!(!dirs.matches("app/build", false));
+  }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::glob::{self, Glob};
+
+// The rules of one .gitignore or .ignore file.
+// Each line is a glob; `!` in front of it un-ignores what an earlier line ignored, blank lines and
+// lines starting with '#' are skipped. The last line that matches a path decides about it.
+// .ignore files use the same syntax and are there for things git should track but a search
+// shouldn't see.
+
+#[derive(Debug, Clone)]
+struct Rule {
+  glob: Glob,
+  negated: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct IgnoreFile {
+  dir: PathBuf, //the directory the file is in; its globs are relative to it
+  rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+  pub fn parse(dir: &Path, contents: &str) -> IgnoreFile {
+    let rules = contents
+      .lines()
+      .map(str::trim_end)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(|line| match line.strip_prefix('!') {
+        Some(rest) => Rule { glob: Glob::new(rest), negated: true },
+        None => Rule { glob: Glob::new(line), negated: false },
+      })
+      .collect();
+    IgnoreFile { dir: dir.to_path_buf(), rules }
+  }
+
+  //the file called `name` in `dir`, if there is one; one that can't be read only gets a warning,
+  //it's not worth stopping the whole search over
+  pub fn read(dir: &Path, name: &str) -> Option<IgnoreFile> {
+    let path = dir.join(name);
+    match fs::read_to_string(&path) {
+      Ok(contents) => Some(IgnoreFile::parse(dir, &contents)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+      Err(e) => {
+        eprintln!("minigrep: warning: {}: {e}, searching as if it weren't there", path.display());
+        None
+      }
+    }
+  }
+
+  //Some(true) if the path is ignored, Some(false) if a `!` line keeps it,
+  //None if nothing in this file is about it
+  pub fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+    let relative = glob::relative_path(&self.dir, path)?;
+    self.rules.iter().rev().find(|rule| rule.glob.matches(&relative, is_dir)).map(|rule| !rule.negated)
+  }
+}
+
+//whether `path` is ignored by the files that apply to it, which are given outermost first:
+//a file deeper in the tree overrides the ones above it
+pub fn is_ignored(files: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+  files.iter().rev().find_map(|file| file.decide(path, is_dir)).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn last_matching_rule_wins() {
+    let root = IgnoreFile::parse(Path::new("/repo"), "# build output\ntarget/\n*.log\n!keep.log\n\n/notes.txt\n");
+    let nested = IgnoreFile::parse(Path::new("/repo/src"), "!*.log\ngenerated.rs\n");
+    let files = [root.clone()];
+
+    assert!(is_ignored(&files, Path::new("/repo/target"), true));
+    assert!(!is_ignored(&files, Path::new("/repo/target"), false));
+    assert!(is_ignored(&files, Path::new("/repo/a/b/debug.log"), false));
+    assert!(!is_ignored(&files, Path::new("/repo/a/keep.log"), false));
+    assert!(is_ignored(&files, Path::new("/repo/notes.txt"), false));
+    assert!(!is_ignored(&files, Path::new("/repo/src/notes.txt"), false));
+
+    let files = [root, nested];
+    assert!(!is_ignored(&files, Path::new("/repo/src/debug.log"), false));
+    assert!(is_ignored(&files, Path::new("/repo/src/generated.rs"), false));
+    assert!(!is_ignored(&files, Path::new("/repo/generated.rs"), false));
+  }
+
+  #[test]
+  fn an_ignore_file_that_cant_be_read_counts_as_absent() {
+    let root = std::env::temp_dir().join(format!("minigrep-ignore-{}", std::process::id()));
+    fs::create_dir_all(root.join(".gitignore")).unwrap(); //a directory, which read_to_string refuses
+    fs::write(root.join(".ignore"), "*.log\n").unwrap();
+
+    let gitignore = IgnoreFile::read(&root, ".gitignore");
+    let ignore = IgnoreFile::read(&root, ".ignore");
+    let missing = IgnoreFile::read(&root, ".missing");
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(gitignore.is_none());
+    assert!(ignore.is_some_and(|file| file.decide(&root.join("x.log"), false) == Some(true)));
+    assert!(missing.is_none());
+  }
+}
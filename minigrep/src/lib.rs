@@ -1,194 +1,207 @@
-use std::error::Error; //will be used to return errors from the run function
-use std::fs; //to handle file system operations
-use std::env; //to read the environment variables with the var() function
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::thread;
 
-#[derive(Debug)]
-pub struct Config {
-    pub query: String,
-    pub file_path: String,
-    pub ignore_case: bool,
-    pub cli_case: String,
-}
+pub mod aho_corasick; //many literal patterns in one pass, for -e/-f
+pub mod buffer; //the fast path: whole files, memory-mapped, scanned for a literal query with SIMD
+pub mod casefold; //Unicode case folding for -i, without lowercased copies of the lines
+pub mod config; //command line parsing into a Config
+pub mod decompress; //-z, gzip, bzip2, xz and zstd files decompressed as they're read
+pub mod defaults; //the config file and MINIGREP_* variables the command line is layered on
+pub mod error; //the Error returned by run()
+pub mod fuzzy; //--fuzzy, approximate matching within K edits of the query
+pub mod glob; //shell style globs for --include/--exclude and ignore files
+pub mod ignore; //.gitignore and .ignore rules
+pub mod matcher; //decides whether, and where, a line matches
+pub mod multiline; //-U, matches that go over several lines, grouped into blocks of lines
+pub mod regex; //small regex engine behind the -E/--regex flag
+pub mod replace; //--replace, and rewriting the files with --in-place
+pub mod stats; //--stats, the totals and the time each phase of a run took
+pub mod searcher; //the library API: a Searcher built from options, giving back lazy iterators of matches
+pub mod walk; //turns the paths from the command line into the list of files to search
+pub mod output; //formats the hits: path and line number prefixes, context lines
+pub mod parallel; //searches several files at once on a pool of threads (-j)
+pub mod stream; //line by line reading for stdin and files too big to load at once
+pub mod tui; //--tui, the hits in a full-screen list that follows the query as it's typed
+#[cfg(target_os = "linux")]
+pub mod watch; //--watch, following the files with inotify and searching what's written to them
+
+pub use config::{ArgsError, CaseMode, ColorChoice, Config, USAGE};
+pub use defaults::Defaults;
+pub use error::Error;
+pub use matcher::{Boundary, Matcher};
+pub use searcher::{Match, Searcher, SearcherBuilder};
+use output::{OutputMode, Printer, Summary};
+use glob::Glob;
+use parallel::ReadOptions;
+use replace::{Replacement, RewriteOptions};
+use stats::Timer;
+use walk::WalkOptions;
 
-impl Config {
-    //by having 'static as the lifetime, we're saying that error message will live as long as the program
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            return Err("Not enough arguments");
-        }
-        let query = args[1].clone();
-        let file_path = args[2].clone();
-        
-        let optional_cli_arg;
-        
-        if args.len() == 4 {
-            optional_cli_arg = args[3].clone();
-        } else {
-            optional_cli_arg = String::from("");
-        }
-    
-        let cli_case;
-        if optional_cli_arg == "-s" {
-            cli_case = "sensitive"
-        } else if optional_cli_arg == "-i" {
-            cli_case = "insensitive"
-        } else {
-            cli_case = "none"
-        }
+//contains the logic of the program from read the files, onwards
+//the Summary lets main tell "nothing matched" apart from success for the exit code
+pub fn run(config: Config) -> Result<Summary, Error> {
+  let mut timer = Timer::start();
+  let insensitive = config.is_case_insensitive();
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok(); //we're just checking if an env var is set- The value is not relevant here
-        
-        //wrap the values in a Config struct in the Ok variant
-        Ok(Config { query, file_path, cli_case: cli_case.to_string(), ignore_case }) //the Config will own the values of query, file_path and ignore_case
+  //the matcher is built once up front, so an invalid regex is reported before any searching happens
+  let searcher = build_searcher(&config, &config.patterns)?;
+  timer.lap("building the matcher");
+
+  //the banner goes to stderr so that stdout only ever carries results, and -q keeps quiet about it too;
+  //--tui has the screen to itself
+  if config.output_mode != OutputMode::Quiet && !config.tui {
+    if config.regex && insensitive {
+      eprintln!("Insensitive regex search");
+    } else if config.regex {
+      eprintln!("Sensitive regex search");
+    } else if config.case_mode.is_none() && insensitive {
+      eprintln!("Insensitive search");
+    } else if config.case_mode.is_some() && !insensitive {
+      eprintln!("Sensitive search with CLI argument");
+    } else if config.case_mode.is_some() {
+      eprintln!("Insensitive search with CLI argument");
+    } else {
+      eprintln!("Sensitive search");
     }
-}
+  }
 
-//contains the logic of the program from read the file, onwards
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-  let contents = fs::read_to_string(config.file_path)?; // the ? will return the error value instead of panicking (in case it fails to read the file)
-  
-  let results = if config.ignore_case {
-    println!("Insensitive search");
-    search_case_insensitive(&config.query, &contents)
-  } else if config.cli_case == "sensitive" {
-    println!("Sensitive search with CLI argument");
-    search(&config.query, &contents)
-  } else if config.cli_case == "insensitive" {
-      println!("Insensitive search with CLI argument");
-      search_case_insensitive(&config.query, &contents)
-  } else {
-    println!("Sensitive search");
-    search(&config.query, &contents)
+  let walk_options = WalkOptions {
+    hidden: config.hidden,
+    no_ignore: config.no_ignore,
+    text: config.text,
+    decompress: config.search_zip,
+    include: config.include.iter().map(|glob| Glob::new(glob)).collect(),
+    exclude: config.exclude.iter().map(|glob| Glob::new(glob)).collect(),
   };
-  
-  for line in results {
-    println!("{line}");
+  let mut stdout = io::stdout().lock();
+  let replacement = config.replace.as_deref().map(|text| Replacement::new(text, config.regex));
+  //--watch walks the directories itself, and again when files are created in them; the files come
+  //and go, so it's the paths that decide whether hits are prefixed with theirs.
+  //Config::build() doesn't take --watch anywhere else
+  #[cfg(target_os = "linux")]
+  if config.watch {
+    let show_path = config.file_paths.len() > 1 || config.file_paths.iter().any(|path| Path::new(path).is_dir());
+    let printer = new_printer(&config, &searcher, show_path);
+    return watch::follow(searcher.matcher(), replacement.as_ref(), &printer, &config.file_paths, &walk_options, &mut stdout);
   }
-  
-  Ok(()) //indicates success and the unit type () indicates that we don't have a value to return
-}
-
-//we connect the signature lifetime to the lifetime of the contents argument, as we're returning a slice of the contents
-//containing the lines that match the query and not the other way around
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    //requirements:
-    let mut results = Vec::new();
-    //Iterate through each line of the contents.
-    for line in contents.lines() { //lines() returns an iterator over the lines of a string
-      //Check whether the line contains our query string.
-      if line.contains(query) {
-        //If it does, add it to the list of values we’re returning.
-        results.push(line);
-      }
-      //If it doesn’t, do nothing.
+  let walked = walk::collect_files(&config.file_paths, &walk_options);
+  let files = walked.files;
+  timer.lap("walking the paths");
+  if config.tui {
+    return browse(&config, &files, walked.unreadable);
+  }
+  //like grep, hits are only prefixed with their path when there is more than one place they could come from
+  //("-" is stdin, which is also what an empty list of paths turns into)
+  let show_path = files.len() > 1 || config.file_paths.iter().any(|path| Path::new(path).is_dir());
+  let mut printer = new_printer(&config, &searcher, show_path);
+  let jobs = match config.threads {
+    0 => thread::available_parallelism().map_or(1, |n| n.get()),
+    n => n,
+  };
+  let (summary, unreadable) = match (&replacement, config.in_place) {
+    (Some(replacement), true) => {
+      let options = RewriteOptions { apply: config.apply, color: printer.color, text: config.text, max_count: searcher.max_count() };
+      let rewritten = rewrite_files(searcher.matcher(), replacement, &files, options, &mut stdout)?;
+      timer.lap("rewriting");
+      rewritten
     }
-    //Return the list of results that match
-    results
-}
-
-
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-  //requirements:
-  //parse the query to lowercase and repeat the same steps as in search()
-  let query = query.to_lowercase(); // create a new String with the lowercase version of the query
-  let mut results = Vec::new();
-  
-  for line in contents.lines() {
-    if line.to_lowercase().contains(&query) { 
-      results.push(line); //we'll still be pushing the original line and not the lowercase version used only for comparison
+    _ => {
+      let read = ReadOptions { mmap: config.mmap, decompress: config.search_zip };
+      let unreadable = parallel::search_files(searcher.matcher(), replacement.as_ref(), &mut printer, &files, jobs, read, &mut stdout).map_err(Error::Write)?;
+      printer.finish(&mut stdout).map_err(Error::Write)?;
+      timer.lap("searching");
+      (printer.summary(), unreadable)
     }
+  };
+  //the numbers are printed even when some files couldn't be read, they're about the ones that could
+  if config.stats {
+    stats::report(&mut io::stderr(), &summary, timer.phases(), timer.total()).map_err(Error::Write)?;
   }
-  results
-}
 
-pub fn find_first_insensitive_match<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-  let query = query.to_lowercase();
-  let mut results = Vec::new();
-  
-  for line in contents.lines() {
-    if line.to_lowercase().contains(&query) {
-      results.push(line);
-      break;
-    }
+  //like grep -q, a hit found despite unreadable files still counts as success in quiet mode;
+  //what the walk couldn't read counts along with the files
+  let unreadable = unreadable + walked.unreadable;
+  if unreadable > 0 && !printer.search_done() {
+    return Err(Error::Unreadable { failed: unreadable, total: files.len() + walked.unreadable });
   }
-  results
+  Ok(summary)
 }
 
-pub fn find_first_sensitive_match<'a>(query: &str, contents: &'a str) -> String {
-  let mut found = Vec::new();
-  
-  for line in contents.lines() {
-    if line.contains(query) {
-      for char in line.chars() {
-        if line.contains(char){
-          found.push(query);
-          break;
-        }
-      }
-      break;
-    }
-  }
-
-  println!("Found: {:?}", found);
-  let my_string = found.into_iter().collect::<String>();
-  my_string 
-
+//the Printer for the output options on the command line
+fn new_printer(config: &Config, searcher: &Searcher, show_path: bool) -> Printer {
+  let mut printer = Printer::new(show_path, config.line_numbers, config.before_context, config.after_context);
+  printer.mode = config.output_mode;
+  printer.text = config.text;
+  printer.max_count = searcher.max_count();
+  printer.show_distance = config.fuzzy.is_some();
+  printer.counting = config.stats;
+  printer.color = match config.color {
+    ColorChoice::Always => true,
+    ColorChoice::Never => false,
+    //https://no-color.org: a non-empty NO_COLOR turns off colours that would be on by default
+    ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+  };
+  printer
 }
 
+//the Searcher for some patterns, with every other option from the command line
+fn build_searcher(config: &Config, patterns: &[String]) -> Result<Searcher, Error> {
+  Searcher::builder()
+    .patterns(patterns)
+    .ignore_case(config.ignores_case_of(patterns))
+    .regex(config.regex)
+    .boundary(config.boundary)
+    .invert(config.invert_match)
+    .max_count(config.max_count)
+    .fuzzy(config.fuzzy)
+    .multiline(config.multiline)
+    .build()
+}
 
-
-#[cfg(test)]
-mod tests {
-  use super::*;
-  
-  //the test function describes the desired behavior: given a query and contents, return only lines that contain the query
-  #[test]
-  fn case_sensitive() {
-    let query = "duct";
-    let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Duct tape.";
-    
-    assert_eq!(vec!["safe, fast, productive."], search(query, contents));
-    
+//--tui: the files are read once, then searched again for every change to the query
+fn browse(config: &Config, files: &[PathBuf], unreadable: usize) -> Result<Summary, Error> {
+  if !io::stdout().is_terminal() {
+    return Err(ArgsError::Requires { option: String::from("--tui"), requirement: "a terminal on standard output" }.into());
   }
-  
-  #[test]
-  fn case_insensitive() {
-    let query = "rUsT";
-    let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Trust me.";
+  let (loaded, errors) = tui::load(files, config.text, config.search_zip);
+  let build = |query: &str| {
+    //Config::build() only checked the query on the command line against --fuzzy K
+    if let Some(k) = config.fuzzy.filter(|&k| k >= query.chars().count()) {
+      let expected = "fewer edits than the query has characters";
+      return Err(ArgsError::InvalidValue { option: String::from("--fuzzy"), value: k.to_string(), expected }.into());
+    }
+    build_searcher(config, &[query.to_string()])
+  };
+  let mut browser = tui::Browser::new(loaded, &config.patterns[0], build);
+  tui::browse(&mut browser, config.search_zip)?;
 
-    assert_eq!(vec!["Rust:", "Trust me."], search_case_insensitive(query, contents));
+  //what couldn't be read is told once the terminal is back to normal
+  for e in &errors {
+    eprintln!("minigrep: {}", e.chain());
   }
-  
-  #[test]
-  fn first_insensitive_match() {
-    let query = "rUst";
-    let contents = "\
-  Rust:
-  safe, fast, productive.
-  Pick three.
-  Duct tape.";
-        
-      assert_eq!(vec!["Rust:"], find_first_insensitive_match(query, contents))
+  if unreadable + errors.len() > 0 {
+    return Err(Error::Unreadable { failed: unreadable + errors.len(), total: files.len() + unreadable });
   }
-  
-  #[test]
-  fn first_sensitive_match() {
-    let query = "safe";
-    let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Duct tape.";
-  
-    assert_eq!("safe", find_first_sensitive_match(query, contents));
+  Ok(browser.summary())
+}
+
+//--in-place: one file after the other, since their diffs are printed whole; a file that can't be
+//read is skipped like in a search and counted in the number given back with the Summary, but one
+//that can't be written back stops the run, so the files after it are left as they were
+fn rewrite_files(matcher: &Matcher, replacement: &Replacement, files: &[PathBuf], options: RewriteOptions, out: &mut impl io::Write) -> Result<(Summary, usize), Error> {
+  let mut summary = Summary::default();
+  let mut unreadable = 0;
+  for path in files {
+    match replace::rewrite_file(matcher, replacement, path, options, out) {
+      Ok(file) => summary += file,
+      Err(e @ Error::Read { .. }) => {
+        eprintln!("minigrep: {}", e.chain());
+        unreadable += 1;
+      }
+      Err(e) => return Err(e),
+    }
   }
-  
-}
\ No newline at end of file
+  Ok((summary, unreadable))
+}
@@ -1,20 +1,51 @@
 use std::env; //bring it to scope so we can use args()
 use std::process; //will be used to exit the program if there's an error
 
-use minigrep::Config; // bring the Config struct from the library crate (minigrep - lib.rs) into the binary crate's scope
+use minigrep::{ArgsError, Config, Error, USAGE}; // bring the Config struct from the library crate (minigrep - lib.rs) into the binary crate's scope
 
 fn main() {
     let args: Vec<String> = env::args().collect(); //turn the iterator into a collection of values, in this case a Vector of strings
     // dbg!(args);
     
     let config = Config::build(&args).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err); //eprintln! will print to the stderr instead of stdout
-        process::exit(1) // signaling that the the program has exited with an error (non-zero exit code)
+        if let Error::Args(help @ (ArgsError::Help | ArgsError::Version)) = &err {
+            //asked for explicitly, so it goes to stdout and it's not a failure
+            println!("{help}");
+            process::exit(0)
+        }
+        //eprintln! will print to the stderr instead of stdout
+        match &err {
+            Error::Settings { .. } => eprintln!("Problem reading the settings: {err}"),
+            //"Problem parsing arguments" already says what Error::Args would
+            Error::Args(e) => eprintln!("Problem parsing arguments: {e}"),
+            _ => eprintln!("Problem parsing arguments: {}", err.chain()),
+        }
+        if let Error::Args(_) = err {
+            eprintln!("\n{USAGE}");
+        }
+        process::exit(exit_code(&err)) // signaling that the the program has exited with an error (non-zero exit code)
     });
     
-    //As the success return value of run is (), there is nothing to unwrap so we're only interested in handling the error with if let Err(e)
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    //grep's exit codes, so minigrep works in `if` and `&&` in shell scripts: 0 hit, 1 no hit, 2 and up errors
+    match minigrep::run(config) {
+        Ok(summary) if summary.matched_lines > 0 => process::exit(0),
+        Ok(_) => process::exit(1),
+        Err(e) => {
+            eprintln!("Application error: {}", e.chain());
+            process::exit(exit_code(&e));
+        }
+    }
+}
+
+//one exit code per kind of failure, so a script can tell them apart; bad arguments keep grep's 2
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Args(_) | Error::Settings { .. } => 2,
+        Error::PatternFile { .. } => 3,
+        Error::Pattern(_) => 4,
+        Error::Read { .. } => 5,
+        Error::Unreadable { .. } => 6,
+        Error::Write(_) => 7,
+        Error::Rewrite { .. } => 8,
     }
 }
@@ -0,0 +1,223 @@
+use crate::aho_corasick::AhoCorasick;
+use crate::casefold;
+use crate::fuzzy::Fuzzy;
+use crate::regex::Regex;
+
+//where a match has to sit in its line
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Boundary {
+  #[default]
+  Anywhere,
+  Word, //-w: no word character right before or after the match
+  Line, //-x: the match is the whole line
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+  Sensitive(String),
+  Insensitive(Vec<char>), //the query case folded, so it is folded once instead of on every comparison
+  Regex(Regex),
+  Many(AhoCorasick), //several literal patterns (-e/-f), searched for in a single pass
+  Fuzzy(Fuzzy), //--fuzzy: within a few edits of the query
+}
+
+//how each line gets tested, decided once from the Config before any file is read
+#[derive(Debug, Clone)]
+pub struct Matcher {
+  pattern: Pattern,
+  boundary: Boundary,
+  invert: bool,
+  multiline: bool, //-U: the text searched is a whole file, not one line of it
+}
+
+impl Matcher {
+  pub fn sensitive(query: &str) -> Matcher {
+    Matcher::from_pattern(Pattern::Sensitive(query.to_string()))
+  }
+
+  pub fn insensitive(query: &str) -> Matcher {
+    Matcher::from_pattern(Pattern::Insensitive(casefold::fold_str(query)))
+  }
+
+  //any of several literal patterns; a single pattern doesn't need the automaton
+  pub fn literals(patterns: &[String], ignore_case: bool) -> Matcher {
+    match patterns {
+      [query] if ignore_case => Matcher::insensitive(query),
+      [query] => Matcher::sensitive(query),
+      _ => Matcher::from_pattern(Pattern::Many(AhoCorasick::new(patterns, ignore_case))),
+    }
+  }
+
+  //--fuzzy K: parts of the line at most `max_distance` edits away from the query
+  pub fn fuzzy(query: &str, max_distance: usize, ignore_case: bool) -> Matcher {
+    Matcher::from_pattern(Pattern::Fuzzy(Fuzzy::new(query, max_distance, ignore_case)))
+  }
+
+  pub fn regex(re: Regex) -> Matcher {
+    Matcher::from_pattern(Pattern::Regex(re))
+  }
+
+  fn from_pattern(pattern: Pattern) -> Matcher {
+    Matcher { pattern, boundary: Boundary::Anywhere, invert: false, multiline: false }
+  }
+
+  pub fn with_boundary(mut self, boundary: Boundary) -> Matcher {
+    //a regex has to know its boundary while matching, or `-w 'ab|abc'` would give up on "abc"
+    //after trying the "ab" branch
+    if let Pattern::Regex(re) = &self.pattern {
+      self.pattern = Pattern::Regex(re.with_boundary(boundary));
+    }
+    self.boundary = boundary;
+    self
+  }
+
+  //-U: matches can go over several lines, so the text handed in is all of them; `^`, `$` and -x
+  //then go by the lines in it
+  pub fn multiline(mut self, multiline: bool) -> Matcher {
+    if let Pattern::Regex(re) = &self.pattern {
+      self.pattern = Pattern::Regex(re.with_multiline(multiline));
+    }
+    self.multiline = multiline;
+    self
+  }
+
+  pub fn is_multiline(&self) -> bool {
+    self.multiline
+  }
+
+  //-v: select the lines that do NOT match
+  pub fn inverted(mut self, invert: bool) -> Matcher {
+    self.invert = invert;
+    self
+  }
+
+  pub fn is_inverted(&self) -> bool {
+    self.invert
+  }
+
+  //whether the line is one we're looking for, taking -v into account
+  pub fn is_selected(&self, line: &str) -> bool {
+    self.find(line).is_some() != self.invert
+  }
+
+  //byte range of the first match in the line, if there is one (-v doesn't change what a match is)
+  pub fn find(&self, line: &str) -> Option<(usize, usize)> {
+    self.find_at(line, 0)
+  }
+
+  //first match starting at or after the byte offset `at`
+  pub fn find_at(&self, line: &str, at: usize) -> Option<(usize, usize)> {
+    let (boundary, multiline) = (self.boundary, self.multiline);
+    match &self.pattern {
+      Pattern::Regex(re) => re.find_at(line, at), //the regex needs the whole line to check ^ and \b
+      Pattern::Many(ac) => ac.find_at_where(line, at, |start, end| boundary.fits(line, start, end, multiline)),
+      //a fuzzy match can be longer or shorter than the query, so with -x it's the whole line that's compared
+      Pattern::Fuzzy(fuzzy) if boundary == Boundary::Line => {
+        (at == 0 && fuzzy.distance(line) <= fuzzy.max_distance()).then_some((0, line.len()))
+      }
+      _ if boundary == Boundary::Anywhere => self.find_literal_at(line, at),
+      //a single literal has one length, so the leftmost occurrence is the only one that could start at 0
+      _ if boundary == Boundary::Line && !multiline => self.find_literal_at(line, at).filter(|&(start, end)| boundary.fits(line, start, end, false)),
+      _ => {
+        let mut from = at;
+        loop {
+          let (start, end) = self.find_literal_at(line, from)?;
+          if boundary.fits(line, start, end, multiline) {
+            return Some((start, end));
+          }
+          //"to" in "total to" fails at 0, so look again from the next character
+          from = start + line[start..].chars().next()?.len_utf8();
+        }
+      }
+    }
+  }
+
+  fn find_literal_at(&self, line: &str, at: usize) -> Option<(usize, usize)> {
+    let shift = |(start, end): (usize, usize)| (start + at, end + at);
+    match &self.pattern {
+      Pattern::Sensitive(query) => line[at..].find(query.as_str()).map(|start| shift((start, start + query.len()))),
+      Pattern::Insensitive(query) => casefold::find_folded(line, at, query),
+      Pattern::Regex(re) => re.find_at(line, at),
+      Pattern::Many(ac) => ac.find_at(line, at),
+      Pattern::Fuzzy(fuzzy) => fuzzy.find_at(line, at),
+    }
+  }
+
+  //how many edits the matched text is from the query: always 0 except with --fuzzy
+  pub fn distance(&self, matched: &str) -> usize {
+    match &self.pattern {
+      Pattern::Fuzzy(fuzzy) => fuzzy.distance(matched),
+      _ => 0,
+    }
+  }
+
+  //the text every selected line has to contain, when that's all there is to the search, so a whole
+  //buffer can be scanned for it before looking at lines (see buffer.rs); -w and -x are still
+  //checked on each of those lines, but -v needs the lines without it, so there's none
+  pub fn required_literal(&self) -> Option<&str> {
+    match &self.pattern {
+      Pattern::Sensitive(query) if !self.invert && !self.multiline && !query.is_empty() && !query.contains('\n') => Some(query),
+      _ => None,
+    }
+  }
+
+  pub fn is_fuzzy(&self) -> bool {
+    matches!(self.pattern, Pattern::Fuzzy(_))
+  }
+
+  //the match at or after `at` with the spans of its capture groups after it; only a regex has
+  //groups, so for the other patterns that's just the match
+  pub fn captures_at(&self, line: &str, at: usize) -> Option<Vec<Option<(usize, usize)>>> {
+    match &self.pattern {
+      Pattern::Regex(re) => re.captures_at(line, at),
+      _ => self.find_at(line, at).map(|span| vec![Some(span)]),
+    }
+  }
+
+  //every non-overlapping match in the line, left to right, appended to `spans`
+  pub fn find_all(&self, line: &str, spans: &mut Vec<(usize, usize)>) {
+    let mut at = 0;
+    let mut last_end = None;
+    while let Some((start, end)) = self.find_at(line, at) {
+      //an empty match right where the previous match ended isn't a new match (o* on "bog" is 0..0, 1..2, 3..3)
+      if start < end || last_end != Some(end) {
+        spans.push((start, end));
+        last_end = Some(end);
+      }
+      //after an empty match, step over one character so the same empty match isn't found forever
+      at = match line[end..].chars().next() {
+        _ if start < end => end,
+        Some(c) => end + c.len_utf8(),
+        None => break,
+      };
+    }
+  }
+}
+
+impl Boundary {
+  //whether the span start..end of the line is somewhere this boundary allows; with -U `line` is
+  //several lines, and -x takes whole lines of it
+  fn fits(self, line: &str, start: usize, end: usize, multiline: bool) -> bool {
+    match self {
+      Boundary::Anywhere => true,
+      Boundary::Word => !ends_in_word(&line[..start]) && !starts_with_word(&line[end..]),
+      Boundary::Line if multiline => {
+        let (before, after) = (&line[..start], &line[end..]);
+        (before.is_empty() || before.ends_with('\n')) && (after.is_empty() || after.starts_with('\n') || after.starts_with("\r\n"))
+      }
+      Boundary::Line => start == 0 && end == line.len(),
+    }
+  }
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+fn ends_in_word(text: &str) -> bool {
+  text.chars().next_back().is_some_and(is_word_char)
+}
+
+fn starts_with_word(text: &str) -> bool {
+  text.chars().next().is_some_and(is_word_char)
+}
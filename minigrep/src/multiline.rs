@@ -0,0 +1,145 @@
+use memchr::{memchr, memchr_iter, memrchr};
+
+use crate::Matcher;
+
+// -U/--multiline: the pattern is matched against the whole text instead of one line at a time, so
+// a match can go on over line breaks, like a function signature split over several lines:
+//   minigrep -U -E 'fn \w+\([^)]*\)' src
+// Whatever a match touches is reported as one block of lines, starting at the line the match starts
+// on. Matches that start on a line of the block are added to it, so a line is never in two blocks.
+// The line numbers are counted from one block to the next, only over the text in between.
+
+//the lines one or more matches are on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block<'a> {
+  pub number: usize, //of the first line, counted from 1
+  pub offset: usize, //byte offset of the first line in the text
+  pub text: &'a str, //the lines, without the line ending of the last one
+  pub spans: Vec<(usize, usize)>, //byte ranges of the matches in `text`, in order
+  pub end: usize, //byte offset of the line after the block, or the length of the text
+}
+
+impl Block<'_> {
+  pub fn lines(&self) -> usize {
+    memchr_iter(b'\n', self.text.as_bytes()).count() + 1
+  }
+}
+
+//the blocks of `text` the matcher finds something in, in order
+pub fn blocks<'m, 'a>(matcher: &'m Matcher, text: &'a str) -> Blocks<'m, 'a> {
+  Blocks { matcher, text, at: 0, last_end: None, pending: None, counted: (0, 1) }
+}
+
+pub struct Blocks<'m, 'a> {
+  matcher: &'m Matcher,
+  text: &'a str,
+  at: usize, //where the search for the next match starts
+  last_end: Option<usize>,
+  pending: Option<(usize, usize)>, //a match found while looking for the end of the last block
+  counted: (usize, usize), //the start of a line and its number, where counting goes on from
+}
+
+impl<'a> Blocks<'_, 'a> {
+  //the next match, with the same rules for empty matches as Matcher::find_all
+  fn next_match(&mut self) -> Option<(usize, usize)> {
+    let text = self.text;
+    loop {
+      if self.at > text.len() {
+        return None;
+      }
+      let (start, end) = self.matcher.find_at(text, self.at)?;
+      //an empty match after the last '\n' would be on a line str::lines() doesn't have
+      if start == text.len() && (text.is_empty() || text.ends_with('\n')) {
+        return None;
+      }
+      self.at = match text[end..].chars().next() {
+        _ if start < end => end,
+        Some(c) => end + c.len_utf8(),
+        None => text.len() + 1,
+      };
+      if start < end || self.last_end != Some(end) {
+        self.last_end = Some(end);
+        return Some((start, end));
+      }
+    }
+  }
+
+  //where the last line of a match ends: the '\n' after it, or the end of the text
+  fn line_end(&self, (start, end): (usize, usize)) -> usize {
+    //a match that takes a line's '\n' with it is still on that line, not the next one
+    let last = if end > start { end - 1 } else { start };
+    memchr(b'\n', &self.text.as_bytes()[last..]).map_or(self.text.len(), |i| last + i)
+  }
+}
+
+impl<'a> Iterator for Blocks<'_, 'a> {
+  type Item = Block<'a>;
+
+  fn next(&mut self) -> Option<Block<'a>> {
+    let bytes = self.text.as_bytes();
+    let first = self.pending.take().or_else(|| self.next_match())?;
+    let start = memrchr(b'\n', &bytes[..first.0]).map_or(0, |i| i + 1);
+    let number = self.counted.1 + memchr_iter(b'\n', &bytes[self.counted.0..start]).count();
+
+    let mut spans = vec![first];
+    let mut line_end = self.line_end(first);
+    loop {
+      match self.next_match() {
+        Some(found) if found.0 <= line_end => {
+          line_end = line_end.max(self.line_end(found));
+          spans.push(found);
+        }
+        found => {
+          self.pending = found;
+          break;
+        }
+      }
+    }
+
+    let end = (line_end + 1).min(bytes.len());
+    let mut text = &self.text[start..line_end];
+    if line_end < bytes.len() {
+      text = text.strip_suffix('\r').unwrap_or(text);
+    }
+    //a match of the line ending itself ends where the text of the line does
+    let spans = spans.iter().map(|&(from, to)| ((from - start).min(text.len()), (to - start).min(text.len()))).collect();
+    let block = Block { number, offset: start, text, spans, end };
+    self.counted = (end, number + block.lines());
+    Some(block)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::regex::Regex;
+
+  //the first line, the lines and the matches of each block
+  type Found = (usize, String, Vec<(usize, usize)>);
+
+  fn found(pattern: &str, text: &str) -> Vec<Found> {
+    let matcher = Matcher::regex(Regex::build(pattern, false).unwrap()).multiline(true);
+    blocks(&matcher, text).map(|block| (block.number, block.text.to_string(), block.spans)).collect()
+  }
+
+  #[test]
+  fn matches_over_lines_come_in_blocks() {
+    let code = "use std::fs;\r\n\r\nfn search(\r\n  query: &str,\r\n) -> bool {\r\n  fn nested() {}\r\n}\r\n";
+    //the first match takes three lines, the second starts on the first line after them
+    assert_eq!(
+      vec![(3, String::from("fn search(\r\n  query: &str,\r\n) -> bool {"), vec![(0, 39)]), (6, String::from("  fn nested() {}"), vec![(2, 15)])],
+      found(r"fn \w+\([^)]*\)[^{]*\{", code)
+    );
+    //two matches on one line, and one that starts on the last line of the block, make one block
+    assert_eq!(vec![(1, String::from("ab ab\nab"), vec![(0, 2), (3, 8)])], found(r"ab(\nab)?", "ab ab\nab"));
+    //a match that ends with the '\n' is on the line the '\n' ends
+    assert_eq!(vec![(2, String::from("b"), vec![(0, 1)]), (3, String::from("b"), vec![(0, 1)])], found("b\n", "a\nb\nb\n"));
+    //^ and $ by lines, and no empty match after the last line
+    let lines: Vec<usize> = found("^", "a\n\nb\n").iter().map(|(number, ..)| *number).collect();
+    assert_eq!(vec![1, 2, 3], lines);
+    //-x takes whole lines, a literal can have a '\n' in it
+    let literal = Matcher::sensitive("b\nc").with_boundary(crate::Boundary::Line).multiline(true);
+    let texts: Vec<&str> = blocks(&literal, "ab\nc\nb\nc\n").map(|block| block.text).collect();
+    assert_eq!(vec!["b\nc"], texts);
+  }
+}
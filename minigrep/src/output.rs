@@ -0,0 +1,554 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::AddAssign;
+use std::path::Path;
+
+use crate::Match;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+  #[default]
+  Lines, //grep style text, with optional path and line number prefixes and context
+  Json, //one JSON object per hit plus a summary at the end, for other programs to consume
+  Count, //-c: the number of matching lines in each file
+  FilesWithMatches, //-l: just the names of the files with at least one hit
+  FilesWithoutMatch, //-L: just the names of the files without any
+  Quiet, //-q: nothing at all, the exit code says whether there was a hit
+}
+
+impl OutputMode {
+  //modes where one hit already tells us everything we need to know about a file
+  fn stops_at_first_hit(self) -> bool {
+    matches!(self, OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch | OutputMode::Quiet)
+  }
+}
+
+//grep's default colours: bold red matches, magenta paths, green line numbers, cyan separators
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const PATH_COLOR: &str = "\x1b[35m";
+const LINE_NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+//one line of input as the printer sees it; with -U a hit can be several lines, one block of
+//them with the matches that go over the line breaks (see multiline.rs)
+#[derive(Debug, Clone, Copy)]
+pub struct Line<'a> {
+  pub number: usize, //counted from 1, of the first line
+  pub offset: usize, //byte offset of the start of the line in its file
+  pub text: &'a str,
+  pub is_hit: bool,
+  pub spans: &'a [(usize, usize)], //byte ranges of the matches in the line, empty for other lines
+  pub distance: usize, //--fuzzy: edits between the query and the closest match in the line
+}
+
+//decides how each hit is written out: with or without its path and line number,
+//and with how many lines of context around it.
+//Lines are fed in one at a time, so it works the same for a streamed file as for one in memory;
+//the only lines it keeps are the last before_context ones, in case a hit follows them.
+#[derive(Debug, Default)]
+pub struct Printer {
+  pub show_path: bool,
+  pub line_numbers: bool,
+  pub before_context: usize,
+  pub after_context: usize,
+  pub mode: OutputMode,
+  pub color: bool, //wrap matches and prefixes in ANSI escapes; run() decides this from --color
+  pub text: bool, //-a: print the lines of binary files like any others
+  pub max_count: Option<usize>, //-m: hits after this many in a file are treated like any other line
+  pub show_distance: bool, //--fuzzy: put the edit distance in front of each hit
+  pub counting: bool, //--stats: the totals have to be exact, not only as exact as the output needs
+  path: String, //display form of the file being printed
+  printed_group: bool, //whether anything was printed yet, so we know when a "--" separator is needed
+  last_printed: Option<usize>, //line number of the last line printed from the current file
+  before: VecDeque<(usize, String)>, //recent lines that weren't printed, oldest first
+  after_left: usize, //context lines still owed to the last hit
+  hits_in_file: usize,
+  binary: bool, //the current file has a NUL byte in it
+  binary_reported: bool, //"Binary file ... matches" was printed for the current file
+  stats: Summary,
+}
+
+//totals for the summary record that ends the JSON output, and for --stats
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Summary {
+  pub files_searched: usize,
+  pub files_matched: usize,
+  pub matched_lines: usize,
+  pub matches: usize,
+  pub bytes_read: u64, //from the files as they are on disk, compressed or not
+  pub lines_examined: usize, //gone through by the matcher or, with the fast path, by memmem
+}
+
+impl AddAssign for Summary {
+  fn add_assign(&mut self, other: Summary) {
+    self.files_searched += other.files_searched;
+    self.files_matched += other.files_matched;
+    self.matched_lines += other.matched_lines;
+    self.matches += other.matches;
+    self.bytes_read += other.bytes_read;
+    self.lines_examined += other.lines_examined;
+  }
+}
+
+impl Printer {
+  pub fn new(show_path: bool, line_numbers: bool, before_context: usize, after_context: usize) -> Printer {
+    Printer { show_path, line_numbers, before_context, after_context, ..Default::default() }
+  }
+
+  //JSON records, colours and the count of matches for --stats need every match in the line,
+  //otherwise it's enough to know there is one
+  pub fn needs_all_spans(&self) -> bool {
+    self.mode == OutputMode::Json || (self.color && self.mode == OutputMode::Lines) || self.counting
+  }
+
+  pub fn summary(&self) -> Summary {
+    self.stats
+  }
+
+  //a printer with the same settings and nothing printed yet, for searching files on another thread
+  pub fn for_worker(&self) -> Printer {
+    let Printer { show_path, line_numbers, before_context, after_context, mode, color, text, max_count, show_distance, counting, .. } = *self;
+    Printer { show_path, line_numbers, before_context, after_context, mode, color, text, max_count, show_distance, counting, ..Default::default() }
+  }
+
+  //writes out what a worker printer printed for one file into `output`, as if this printer had
+  //searched the file itself; files have to be appended in order
+  pub fn append(&mut self, out: &mut impl Write, worker: &Printer, output: &[u8]) -> io::Result<()> {
+    //the worker didn't know whether anything was printed before its file
+    if self.has_context() && self.printed_group && worker.printed_group {
+      writeln!(out, "{}", self.paint(SEPARATOR_COLOR, "--"))?;
+    }
+    out.write_all(output)?;
+    self.printed_group |= worker.printed_group;
+    self.stats += worker.stats;
+    Ok(())
+  }
+
+  //whether lines without a hit can end up printed, so every line has to be fed in
+  pub fn has_context(&self) -> bool {
+    self.mode == OutputMode::Lines && (self.before_context > 0 || self.after_context > 0)
+  }
+
+  //true once the rest of the current file can't change the output, so the caller can stop reading it
+  pub fn file_done(&self) -> bool {
+    (self.mode.stops_at_first_hit() && self.hits_in_file > 0)
+      || self.binary_reported
+      || (self.max_reached() && self.after_left == 0) //like grep -m, the context after the last hit is still printed
+  }
+
+  fn max_reached(&self) -> bool {
+    self.max_count.is_some_and(|max| self.hits_in_file >= max)
+  }
+
+  //true once the rest of the input can't change the output: -q only cares whether there's a hit at all
+  pub fn search_done(&self) -> bool {
+    self.mode == OutputMode::Quiet && self.stats.matched_lines > 0
+  }
+
+  //call before feeding the lines of the next file, so context doesn't leak from one file into another
+  pub fn start_file(&mut self, path: &Path) {
+    self.path = path.display().to_string();
+    self.last_printed = None;
+    self.before.clear();
+    self.after_left = 0;
+    self.hits_in_file = 0;
+    self.binary = false;
+    self.binary_reported = false;
+    self.stats.files_searched += 1;
+  }
+
+  //what the search of the current file went through, for --stats
+  pub fn examined(&mut self, bytes: u64, lines: usize) {
+    self.stats.bytes_read += bytes;
+    self.stats.lines_examined += lines;
+  }
+
+  //call when the current file turns out to be binary: from then on a hit only prints
+  //"Binary file ... matches", unless -a asked for the lines anyway
+  pub fn binary_file(&mut self) {
+    self.binary = !self.text;
+  }
+
+  //feeds every line of the current file in order, hit or not
+  //the output is a parameter instead of a hardcoded stdout so the tests can print into a Vec<u8>
+  pub fn line(&mut self, out: &mut impl Write, line: Line) -> io::Result<()> {
+    let line = Line { is_hit: line.is_hit && !self.max_reached(), ..line };
+    let lines = line.text.matches('\n').count() + 1;
+    if line.is_hit {
+      if self.hits_in_file == 0 {
+        self.stats.files_matched += 1;
+      }
+      //-c and -m count lines, so a block counts as all the lines in it
+      self.hits_in_file += lines;
+      self.stats.matched_lines += lines;
+      self.stats.matches += line.spans.len();
+    }
+
+    match self.mode {
+      OutputMode::Lines if self.binary => {
+        if line.is_hit {
+          self.binary_reported = true;
+          return writeln!(out, "Binary file {} matches", self.path);
+        }
+        return Ok(());
+      }
+      OutputMode::Lines => {}
+      OutputMode::Json if line.is_hit => return self.print_json(out, &line),
+      OutputMode::FilesWithMatches if line.is_hit && self.hits_in_file == 1 => return writeln!(out, "{}", self.path),
+      _ => return Ok(()), //the other modes only print something once the file is finished
+    }
+
+    if line.is_hit {
+      if self.has_context() {
+        //a new block starts unless it carries straight on from the last printed line
+        let first = self.before.front().map_or(line.number, |(n, _)| *n);
+        let continues = self.last_printed.is_some_and(|last| first <= last + 1);
+        if self.printed_group && !continues {
+          writeln!(out, "{}", self.paint(SEPARATOR_COLOR, "--"))?;
+        }
+        while let Some((n, text)) = self.before.pop_front() {
+          self.print_line(out, n, &text, None, None)?;
+        }
+      }
+      match lines {
+        1 => self.print_line(out, line.number, line.text, Some(line.spans), Some(line.distance))?,
+        _ => self.print_block(out, &line)?,
+      }
+      self.printed_group = true;
+      self.last_printed = Some(line.number + lines - 1);
+      self.after_left = self.after_context;
+    } else if self.after_left > 0 {
+      self.print_line(out, line.number, line.text, None, None)?;
+      self.last_printed = Some(line.number);
+      self.after_left -= 1;
+    } else if self.before_context > 0 {
+      //recycle the oldest buffered line's allocation instead of allocating a new String per line
+      let mut text = match self.before.len() == self.before_context {
+        true => self.before.pop_front().map(|(_, text)| text).unwrap_or_default(),
+        false => String::new(),
+      };
+      text.clear();
+      text.push_str(line.text);
+      self.before.push_back((line.number, text));
+    }
+    Ok(())
+  }
+
+  //call after the last line of each file
+  pub fn finish_file(&mut self, out: &mut impl Write) -> io::Result<()> {
+    match self.mode {
+      OutputMode::Count if self.show_path => writeln!(out, "{}:{}", self.path, self.hits_in_file),
+      OutputMode::Count => writeln!(out, "{}", self.hits_in_file),
+      OutputMode::FilesWithoutMatch if self.hits_in_file == 0 => writeln!(out, "{}", self.path),
+      _ => Ok(()),
+    }
+  }
+
+  //call once after the last file; only the JSON output has something to add
+  pub fn finish(&mut self, out: &mut impl Write) -> io::Result<()> {
+    if self.mode == OutputMode::Json {
+      let Summary { files_searched, files_matched, matched_lines, matches, .. } = self.stats;
+      writeln!(
+        out,
+        "{{\"type\":\"summary\",\"files_searched\":{files_searched},\"files_matched\":{files_matched},\"matched_lines\":{matched_lines},\"matches\":{matches}}}"
+      )?;
+    }
+    Ok(())
+  }
+
+  //convenience for contents that are already in memory; matches must be in line order,
+  //which is how Searcher::search returns them
+  pub fn print_file(&mut self, out: &mut impl Write, path: &Path, contents: &str, matches: &[Match]) -> io::Result<()> {
+    self.start_file(path);
+    let mut hits = matches.iter().peekable();
+    let mut lines = contents.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+      let hit = hits.next_if(|m| m.line_number == index + 1);
+      let span = hit.map(|m| [(m.start, m.end)]);
+      //a multiline match comes with all the lines it's on, which stand in for the next few lines
+      let text = hit.map_or(line, |m| m.line);
+      if let Some(last) = text.matches('\n').count().checked_sub(1) {
+        lines.nth(last);
+      }
+      self.line(
+        out,
+        Line {
+          number: index + 1,
+          offset: hit.map_or(0, |m| m.line_offset), //only hits are printed with their offset
+          text,
+          is_hit: hit.is_some(),
+          spans: span.as_ref().map_or(&[], |span| &span[..]),
+          distance: hit.map_or(0, |m| m.distance),
+        },
+      )?;
+    }
+    self.finish_file(out)
+  }
+
+  //matching lines use ':' after the path and line number, context lines use '-';
+  //`hit_spans` and `distance` are None for context lines
+  fn print_line(
+    &self,
+    out: &mut impl Write,
+    line_number: usize,
+    text: &str,
+    hit_spans: Option<&[(usize, usize)]>,
+    distance: Option<usize>,
+  ) -> io::Result<()> {
+    let sep = self.paint(SEPARATOR_COLOR, if hit_spans.is_some() { ":" } else { "-" });
+    let mut prefix = String::new();
+    if self.show_path {
+      prefix.push_str(&format!("{}{sep}", self.paint(PATH_COLOR, &self.path)));
+    }
+    if self.line_numbers {
+      prefix.push_str(&format!("{}{sep}", self.paint(LINE_NUMBER_COLOR, &line_number.to_string())));
+    }
+    //"~1:" for a hit one edit away from the query
+    if let Some(distance) = distance.filter(|_| self.show_distance) {
+      prefix.push_str(&format!("{}{sep}", self.paint(LINE_NUMBER_COLOR, &format!("~{distance}"))));
+    }
+
+    match hit_spans {
+      Some(spans) if self.color && !spans.is_empty() => {
+        //copy the text between the matches as it is and paint each match
+        let mut painted = String::with_capacity(text.len() + spans.len() * (MATCH_COLOR.len() + RESET.len()));
+        let mut copied = 0;
+        for &(start, end) in spans {
+          painted.push_str(&text[copied..start]);
+          painted.push_str(&self.paint(MATCH_COLOR, &text[start..end]));
+          copied = end;
+        }
+        painted.push_str(&text[copied..]);
+        writeln!(out, "{prefix}{painted}")
+      }
+      _ => writeln!(out, "{prefix}{text}"),
+    }
+  }
+
+  //-U: a hit that is several lines has each of them printed with its own number, and the part
+  //of the matches that's on it
+  fn print_block(&self, out: &mut impl Write, line: &Line) -> io::Result<()> {
+    let mut start = 0;
+    let mut spans = Vec::new();
+    for (i, text) in line.text.split('\n').enumerate() {
+      let next = start + text.len() + 1;
+      let text = text.strip_suffix('\r').unwrap_or(text);
+      let end = start + text.len();
+      spans.clear();
+      spans.extend(line.spans.iter().filter(|&&(from, to)| from < end && to > start).map(|&(from, to)| (from.max(start) - start, to.min(end) - start)));
+      self.print_line(out, line.number + i, text, Some(&spans), Some(line.distance))?;
+      start = next;
+    }
+    Ok(())
+  }
+
+  fn paint(&self, color: &str, text: &str) -> String {
+    if self.color && !text.is_empty() {
+      format!("{color}{text}{RESET}")
+    } else {
+      text.to_string()
+    }
+  }
+
+  fn print_json(&self, out: &mut impl Write, line: &Line) -> io::Result<()> {
+    let submatches: Vec<String> = line
+      .spans
+      .iter()
+      .map(|&(start, end)| format!("{{\"text\":{},\"start\":{start},\"end\":{end}}}", json_string(&line.text[start..end])))
+      .collect();
+    //the distance is only there for --fuzzy, so the records of exact searches stay as they were
+    let distance = match self.show_distance {
+      true => format!(",\"distance\":{}", line.distance),
+      false => String::new(),
+    };
+    writeln!(
+      out,
+      "{{\"type\":\"match\",\"file\":{},\"line_number\":{},\"byte_offset\":{},\"text\":{},\"submatches\":[{}]{distance}}}",
+      json_string(&self.path),
+      line.number,
+      line.offset,
+      json_string(line.text),
+      submatches.join(",")
+    )
+  }
+}
+
+//a JSON string literal, quotes included
+fn json_string(text: &str) -> String {
+  let mut json = String::with_capacity(text.len() + 2);
+  json.push('"');
+  for c in text.chars() {
+    match c {
+      '"' => json.push_str("\\\""),
+      '\\' => json.push_str("\\\\"),
+      '\n' => json.push_str("\\n"),
+      '\r' => json.push_str("\\r"),
+      '\t' => json.push_str("\\t"),
+      c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+      c => json.push(c),
+    }
+  }
+  json.push('"');
+  json
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Searcher;
+
+  const POEM: &str = "\
+I'm nobody! Who are you?
+Are you nobody, too?
+Then there's a pair of us - don't tell!
+They'd banish us, you know.
+
+How dreary to be somebody!
+How public, like a frog
+To tell your name the livelong day
+To an admiring bog!";
+
+  fn print(printer: &mut Printer, query: &str) -> String {
+    let matches: Vec<Match> = Searcher::new(query).search(POEM).collect();
+    let mut out = Vec::new();
+    printer.print_file(&mut out, Path::new("poem.txt"), POEM, &matches).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn line_numbers_and_paths() {
+    let mut printer = Printer::new(true, true, 0, 0);
+    assert_eq!("poem.txt:2:Are you nobody, too?\n", print(&mut printer, "too"));
+  }
+
+  #[test]
+  fn context_blocks_are_merged_and_separated() {
+    let mut printer = Printer::new(false, true, 1, 1);
+    let expected = "\
+1:I'm nobody! Who are you?
+2-Are you nobody, too?
+--
+5-
+6:How dreary to be somebody!
+7-How public, like a frog
+";
+    assert_eq!(expected, print(&mut printer, "y!"));
+
+    //with two lines either side the blocks 1-3 and 4-8 touch, so they're printed as one
+    let mut printer = Printer::new(false, false, 2, 2);
+    assert_eq!(POEM.lines().take(8).count(), print(&mut printer, "y!").lines().count());
+    assert!(!print(&mut Printer::new(false, false, 2, 2), "y!").contains("--"));
+  }
+
+  #[test]
+  fn max_count_keeps_trailing_context() {
+    let mut printer = Printer { max_count: Some(1), ..Printer::new(false, true, 0, 1) };
+    //line 2 matches too, but after the first hit it's only context
+    assert_eq!("1:I'm nobody! Who are you?\n2-Are you nobody, too?\n", print(&mut printer, "o"));
+    assert!(printer.file_done());
+    assert_eq!(1, printer.summary().matched_lines);
+  }
+
+  #[test]
+  fn separator_between_files() {
+    let mut printer = Printer::new(true, false, 0, 1);
+    let mut out = Vec::new();
+    for path in ["a.txt", "b.txt"] {
+      printer.start_file(Path::new(path));
+      printer.line(&mut out, Line { number: 1, offset: 0, text: "hit", is_hit: true, spans: &[(0, 3)], distance: 0 }).unwrap();
+      printer.line(&mut out, Line { number: 2, offset: 4, text: "after", is_hit: false, spans: &[], distance: 0 }).unwrap();
+    }
+    assert_eq!("a.txt:hit\na.txt-after\n--\nb.txt:hit\nb.txt-after\n", String::from_utf8(out).unwrap());
+  }
+
+  #[test]
+  fn colored_spans() {
+    let mut printer = Printer { color: true, line_numbers: true, ..Default::default() };
+    let mut out = Vec::new();
+    printer.start_file(Path::new("poem.txt"));
+    let line = Line { number: 8, offset: 0, text: "To tell your name", is_hit: true, spans: &[(3, 7), (13, 17)], distance: 0 };
+    printer.line(&mut out, line).unwrap();
+
+    assert_eq!(
+      "\x1b[32m8\x1b[0m\x1b[36m:\x1b[0mTo \x1b[1;31mtell\x1b[0m your \x1b[1;31mname\x1b[0m\n",
+      String::from_utf8(out).unwrap()
+    );
+  }
+
+  #[test]
+  fn counts_and_file_lists() {
+    let mut printer = Printer { mode: OutputMode::Count, show_path: true, ..Default::default() };
+    assert_eq!("poem.txt:2\n", print(&mut printer, "y!"));
+
+    let mut printer = Printer { mode: OutputMode::FilesWithMatches, ..Default::default() };
+    assert_eq!("poem.txt\n", print(&mut printer, "y!"));
+    assert!(printer.file_done());
+    assert_eq!("", print(&mut printer, "nothing"));
+
+    let mut printer = Printer { mode: OutputMode::FilesWithoutMatch, ..Default::default() };
+    assert_eq!("", print(&mut printer, "y!"));
+    assert_eq!("poem.txt\n", print(&mut printer, "nothing"));
+
+    let mut printer = Printer { mode: OutputMode::Quiet, ..Default::default() };
+    assert_eq!("", print(&mut printer, "y!"));
+    assert!(printer.search_done());
+  }
+
+  #[test]
+  fn fuzzy_distances() {
+    let mut printer = Printer { line_numbers: true, show_distance: true, after_context: 1, ..Default::default() };
+    let mut out = Vec::new();
+    printer.line(&mut out, Line { number: 2, offset: 0, text: "Are you nobdy, too?", is_hit: true, spans: &[(8, 13)], distance: 1 }).unwrap();
+    printer.line(&mut out, Line { number: 3, offset: 0, text: "Then there's a pair", is_hit: false, spans: &[], distance: 0 }).unwrap();
+    assert_eq!("2:~1:Are you nobdy, too?\n3-Then there's a pair\n", String::from_utf8(out).unwrap());
+
+    printer.mode = OutputMode::Json;
+    let mut out = Vec::new();
+    printer.line(&mut out, Line { number: 2, offset: 0, text: "nobdy", is_hit: true, spans: &[(0, 5)], distance: 1 }).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with(r#""end":5}],"distance":1}
+"#));
+  }
+
+  #[test]
+  fn multiline_hits_print_every_line() {
+    let mut printer = Printer { line_numbers: true, after_context: 1, color: true, ..Default::default() };
+    let mut out = Vec::new();
+    //the match goes from "fn" to the ")" on the third line
+    let text = "fn search(\r\n  query,\n) {";
+    printer.line(&mut out, Line { number: 3, offset: 0, text, is_hit: true, spans: &[(0, 22)], distance: 0 }).unwrap();
+    printer.line(&mut out, Line { number: 6, offset: 0, text: "}", is_hit: false, spans: &[], distance: 0 }).unwrap();
+    let paint = |color: &str, text: &str| format!("{color}{text}{RESET}");
+    let (hit, context) = (paint(SEPARATOR_COLOR, ":"), paint(SEPARATOR_COLOR, "-"));
+    let number = |n: &str| paint(LINE_NUMBER_COLOR, n);
+    let expected = format!(
+      "{}{hit}{}\n{}{hit}{}\n{}{hit}{} {{\n{}{context}}}\n",
+      number("3"),
+      paint(MATCH_COLOR, "fn search("),
+      number("4"),
+      paint(MATCH_COLOR, "  query,"),
+      number("5"),
+      paint(MATCH_COLOR, ")"),
+      number("6"),
+    );
+    assert_eq!(expected, String::from_utf8(out).unwrap());
+    //-c and -m count each of the lines
+    assert_eq!(3, printer.summary().matched_lines);
+  }
+
+  #[test]
+  fn json_records_and_summary() {
+    let mut printer = Printer { mode: OutputMode::Json, ..Default::default() };
+    let mut out = Vec::new();
+    printer.start_file(Path::new("dir/\"quoted\".txt"));
+    let text = "a\tb a";
+    printer.line(&mut out, Line { number: 3, offset: 10, text, is_hit: true, spans: &[(0, 1), (4, 5)], distance: 0 }).unwrap();
+    printer.line(&mut out, Line { number: 4, offset: 16, text: "x", is_hit: false, spans: &[], distance: 0 }).unwrap();
+    printer.finish(&mut out).unwrap();
+
+    let expected = r#"{"type":"match","file":"dir/\"quoted\".txt","line_number":3,"byte_offset":10,"text":"a\tb a","submatches":[{"text":"a","start":0,"end":1},{"text":"a","start":4,"end":5}]}
+{"type":"summary","files_searched":1,"files_matched":1,"matched_lines":1,"matches":2}
+"#;
+    assert_eq!(expected, String::from_utf8(out).unwrap());
+  }
+}
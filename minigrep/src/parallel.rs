@@ -0,0 +1,524 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::error::Error;
+use crate::output::{Line, Printer};
+use crate::replace::Replacement;
+use crate::buffer::{self, CandidateLines};
+use crate::decompress::{self, Format};
+use crate::multiline;
+use crate::searcher::lines_with_offsets;
+use crate::stream::{self, Counted, LineReader};
+use crate::Matcher;
+
+// Searching many files on several threads (-j).
+// Each worker takes the next file from a shared counter, searches it with a printer of its own
+// into a buffer, and sends the buffer back. The buffers are written out in the order of the
+// files, whichever worker finishes first, so the output is the same as a search on one thread.
+// The price is that a file's output is held in memory until the files before it are printed;
+// with one thread, or a single file, lines go straight to the output as they are found, which
+// keeps `tail -f log | minigrep` printing as it goes.
+
+//how the files are read
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReadOptions {
+  pub mmap: bool, //big files can be memory-mapped (see buffer.rs), off with --no-mmap
+  pub decompress: bool, //-z: compressed files are searched decompressed (see decompress.rs)
+}
+
+//searches every file in order and returns how many couldn't be read; those are reported on stderr
+//and don't stop the search, but an error writing the output does. With a replacement (--replace) the hits
+//are printed with their matches replaced
+pub fn search_files(
+  matcher: &Matcher,
+  replacement: Option<&Replacement>,
+  printer: &mut Printer,
+  files: &[PathBuf],
+  jobs: usize,
+  read: ReadOptions,
+  out: &mut impl Write,
+) -> io::Result<usize> {
+  if jobs <= 1 || files.len() <= 1 {
+    let mut unreadable = 0;
+    for path in files {
+      let mut errors = Vec::new();
+      search_file(matcher, replacement, printer, path, read, out, &mut errors)?;
+      unreadable += report(errors);
+      if printer.search_done() {
+        break;
+      }
+    }
+    return Ok(unreadable);
+  }
+
+  let next_file = AtomicUsize::new(0);
+  let stop = AtomicBool::new(false);
+  let (sender, receiver) = mpsc::channel();
+  thread::scope(|scope| {
+    for _ in 0..jobs.min(files.len()) {
+      let (sender, next_file, stop) = (sender.clone(), &next_file, &stop);
+      let template = printer.for_worker();
+      scope.spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+          let index = next_file.fetch_add(1, Ordering::Relaxed);
+          let Some(path) = files.get(index) else { break };
+          let mut worker = template.for_worker();
+          let mut output = Vec::new();
+          let mut errors = Vec::new();
+          //writing into a Vec<u8> can't fail
+          let _ = search_file(matcher, replacement, &mut worker, path, read, &mut output, &mut errors);
+          //the receiver is gone when the output couldn't be written, so there's no point going on
+          if sender.send((index, worker, output, errors)).is_err() {
+            break;
+          }
+        }
+      });
+    }
+    drop(sender);
+
+    //results come back in whatever order the workers finish; a result waits here until
+    //every file before it has been printed
+    let mut pending = BTreeMap::new();
+    let mut next_to_print = 0;
+    let mut unreadable = 0;
+    for (index, worker, output, errors) in receiver {
+      pending.insert(index, (worker, output, errors));
+      while let Some((worker, output, errors)) = pending.remove(&next_to_print) {
+        next_to_print += 1;
+        if let Err(e) = printer.append(out, &worker, &output) {
+          stop.store(true, Ordering::Relaxed);
+          return Err(e);
+        }
+        unreadable += report(errors);
+        if printer.search_done() {
+          stop.store(true, Ordering::Relaxed);
+          return Ok(unreadable);
+        }
+      }
+    }
+    Ok(unreadable)
+  })
+}
+
+fn report(errors: Vec<Error>) -> usize {
+  for e in &errors {
+    eprintln!("minigrep: {}", e.chain());
+  }
+  errors.len()
+}
+
+//feeds every line of one file to the printer; problems reading the file are added to `errors`
+//so the other files still get searched, problems writing the output are returned
+fn search_file(matcher: &Matcher, replacement: Option<&Replacement>, printer: &mut Printer, path: &Path, read: ReadOptions, out: &mut impl Write, errors: &mut Vec<Error>) -> io::Result<()> {
+  let is_stdin = path == Path::new("-");
+  let name = if is_stdin { Path::new("(standard input)") } else { path };
+  let file = match is_stdin {
+    true => None,
+    false => match File::open(path) {
+      Ok(file) => Some(file),
+      Err(e) => {
+        //one unreadable file shouldn't stop a search over a whole tree, so report it and carry on
+        errors.push(Error::Read { path: path.to_path_buf(), source: e });
+        return Ok(());
+      }
+    },
+  };
+
+  //a literal query can be looked for in the whole file at once, as long as the printer doesn't
+  //need the lines around the hits
+  let needle = matcher.required_literal().filter(|_| !printer.has_context());
+  let mut contents = None;
+  if let (Some(file), Some(_)) = (&file, needle) {
+    match buffer::load(file, read.mmap) {
+      Ok(loaded) => contents = loaded,
+      Err(e) => {
+        errors.push(Error::Read { path: path.to_path_buf(), source: e });
+        return Ok(());
+      }
+    }
+  }
+  //files are otherwise streamed line by line instead of loaded with fs::read_to_string, so a
+  //multi-gigabyte log costs no more memory than its longest line
+  let reader: Box<dyn BufRead> = match (&contents, file, needle) {
+    //UTF-16 has to be decoded first, and compressed files decompressed, so they take the slow way
+    //even when they're already in memory
+    (Some(contents), _, _) if stream::has_byte_order_mark(contents) => Box::new(&contents[..]),
+    (Some(contents), _, _) if read.decompress && Format::detect(contents).is_some() => Box::new(&contents[..]),
+    (Some(contents), _, Some(needle)) => {
+      printer.start_file(name);
+      printer.examined(contents.len() as u64, 0);
+      let mut scratch = Scratch::default();
+      search_buffer(matcher, needle, replacement, printer, contents, out, &mut scratch)?;
+      return printer.finish_file(out);
+    }
+    (_, Some(file), _) => Box::new(BufReader::new(file)),
+    _ => Box::new(io::stdin().lock()),
+  };
+  let counted = Counted::new(reader);
+  let bytes_read = counted.count.clone();
+  let reader: Box<dyn BufRead> = Box::new(counted);
+  let reader = match read.decompress {
+    true => decompress::decompress(reader),
+    false => Ok(reader),
+  };
+  let mut reader = match reader.and_then(stream::decode) {
+    Ok(reader) => reader,
+    Err(e) => {
+      errors.push(Error::Read { path: name.to_path_buf(), source: e });
+      return Ok(());
+    }
+  };
+
+  printer.start_file(name);
+  //-U: a match can go on past the end of any line, so the file is only searched once it's all read
+  if matcher.is_multiline() {
+    let mut contents = Vec::new();
+    match reader.read_to_end(&mut contents) {
+      Ok(_) => search_multiline(matcher, replacement, printer, &String::from_utf8_lossy(&contents), out)?,
+      Err(e) => errors.push(Error::Read { path: name.to_path_buf(), source: e }),
+    }
+    printer.examined(bytes_read.get(), 0);
+    return printer.finish_file(out);
+  }
+  //like grep, a NUL byte means the file is binary: its matching lines aren't printed, since they'd
+  //be garbage on a terminal, just the fact that it matches
+  if reader.fill_buf().is_ok_and(|start| start.contains(&0)) {
+    printer.binary_file();
+  }
+  let mut lines = LineReader::new(reader);
+  let mut scratch = Scratch::default();
+  loop {
+    let (number, offset, text) = match lines.next_line() {
+      Ok(Some(line)) => line,
+      Ok(None) => break,
+      Err(e) => {
+        errors.push(Error::Read { path: name.to_path_buf(), source: e });
+        break;
+      }
+    };
+    //errors writing to stdout (a closed pipe, say) end the whole run rather than just this file
+    search_line(matcher, replacement, printer, (number, offset, text), out, &mut scratch)?;
+    if printer.file_done() {
+      break;
+    }
+  }
+  printer.examined(bytes_read.get(), 0);
+  printer.finish_file(out)
+}
+
+//the fast path: the whole file scanned for the query, and only the lines it's in handed on
+fn search_buffer(
+  matcher: &Matcher,
+  needle: &str,
+  replacement: Option<&Replacement>,
+  printer: &mut Printer,
+  contents: &[u8],
+  out: &mut impl Write,
+  scratch: &mut Scratch,
+) -> io::Result<()> {
+  //the same test for a binary file as the streamed files get: a NUL in the first 8 KiB,
+  //or anywhere up to a hit
+  if memchr::memchr(0, &contents[..contents.len().min(8 * 1024)]).is_some() {
+    printer.binary_file();
+  }
+  let mut candidates = 0;
+  let mut stopped_at = None;
+  for line in CandidateLines::new(contents, needle) {
+    if memchr::memchr(0, &contents[line.skipped.0..line.skipped.1]).is_some() {
+      printer.binary_file();
+    }
+    let text = String::from_utf8_lossy(&contents[line.start..line.end]);
+    search_line(matcher, replacement, printer, (line.number, line.start, &text), out, scratch)?;
+    candidates += 1;
+    if printer.file_done() {
+      stopped_at = Some(line.number);
+      break;
+    }
+  }
+  //--stats: the lines between the candidates were examined too, by memmem instead of the matcher;
+  //they're only counted when asked for, it's another pass over the file
+  if printer.counting {
+    let lines = stopped_at.unwrap_or_else(|| memchr::memchr_iter(b'\n', contents).count() + usize::from(contents.last().is_some_and(|&b| b != b'\n')));
+    printer.examined(0, lines - candidates);
+  }
+  Ok(())
+}
+
+//-U: the blocks of lines the matches are on, each handed to the printer as one line, with the
+//lines in between for the context
+fn search_multiline(matcher: &Matcher, replacement: Option<&Replacement>, printer: &mut Printer, contents: &str, out: &mut impl Write) -> io::Result<()> {
+  if memchr::memchr(0, contents.as_bytes()).is_some() {
+    printer.binary_file();
+  }
+  //the whole text goes to the matcher at once
+  let lines = memchr::memchr_iter(b'\n', contents.as_bytes()).count() + usize::from(!contents.is_empty() && !contents.ends_with('\n'));
+  printer.examined(0, lines);
+  let mut scratch = Scratch::default();
+  let mut next = (1, 0); //the number and offset of the first line the printer hasn't seen
+  let mut blocks = multiline::blocks(matcher, contents);
+  loop {
+    let block = blocks.next();
+    //lines without a match only matter as context
+    if printer.has_context() {
+      let upto = block.as_ref().map_or(contents.len(), |block| block.offset);
+      for (i, (offset, text)) in lines_with_offsets(&contents[next.1..upto]).enumerate() {
+        printer.line(out, Line { number: next.0 + i, offset: next.1 + offset, text, is_hit: false, spans: &[], distance: 0 })?;
+        if printer.file_done() {
+          return Ok(());
+        }
+      }
+    }
+    let Some(block) = block else { return Ok(()) };
+    let line = match replacement {
+      Some(replacement) => {
+        scratch.replaced.clear();
+        scratch.replaced_spans.clear();
+        replacement.apply(matcher, block.text, &block.spans, &mut scratch.replaced, &mut scratch.replaced_spans);
+        Line { number: block.number, offset: block.offset, text: &scratch.replaced, is_hit: true, spans: &scratch.replaced_spans, distance: 0 }
+      }
+      None => Line { number: block.number, offset: block.offset, text: block.text, is_hit: true, spans: &block.spans, distance: 0 },
+    };
+    printer.line(out, line)?;
+    if printer.file_done() {
+      return Ok(());
+    }
+    next = (block.number + block.lines(), block.end);
+  }
+}
+
+//buffers reused from one line to the next
+#[derive(Default)]
+pub(crate) struct Scratch {
+  spans: Vec<(usize, usize)>,
+  replaced: String,
+  replaced_spans: Vec<(usize, usize)>,
+}
+
+//finds the matches in one line and hands it to the printer
+pub(crate) fn search_line(
+  matcher: &Matcher,
+  replacement: Option<&Replacement>,
+  printer: &mut Printer,
+  (number, offset, text): (usize, usize, &str),
+  out: &mut impl Write,
+  scratch: &mut Scratch,
+) -> io::Result<()> {
+  //the first few KiB looked like text, but a NUL further on still gives a binary file away
+  if text.contains('\0') {
+    printer.binary_file();
+  }
+  printer.examined(0, 1);
+  let spans = &mut scratch.spans;
+  spans.clear();
+  if printer.needs_all_spans() || replacement.is_some() {
+    matcher.find_all(text, spans);
+  } else {
+    spans.extend(matcher.find(text));
+  }
+  //with -v the selected lines are the ones without a match, so there's no span to report
+  let is_hit = spans.is_empty() == matcher.is_inverted();
+  if matcher.is_inverted() {
+    spans.clear();
+  }
+  let distance = match matcher.is_fuzzy() {
+    true => spans.iter().map(|&(start, end)| matcher.distance(&text[start..end])).min().unwrap_or(0),
+    false => 0,
+  };
+  let line = match replacement {
+    Some(replacement) if is_hit && !spans.is_empty() => {
+      scratch.replaced.clear();
+      scratch.replaced_spans.clear();
+      replacement.apply(matcher, text, spans, &mut scratch.replaced, &mut scratch.replaced_spans);
+      Line { number, offset, text: &scratch.replaced, is_hit, spans: &scratch.replaced_spans, distance }
+    }
+    _ => Line { number, offset, text, is_hit, spans, distance },
+  };
+  printer.line(out, line)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::output::OutputMode;
+  use std::fs;
+
+  #[test]
+  fn output_keeps_file_order_on_any_number_of_threads() {
+    let root = std::env::temp_dir().join(format!("minigrep-parallel-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let mut files = Vec::new();
+    for i in 0..20 {
+      let path = root.join(format!("{i:02}.txt"));
+      //the first files are the longest, so with several threads the later ones finish first
+      fs::write(&path, "filler\n".repeat((20 - i) * 500) + &format!("hit {i}\nfiller\nhit again\n")).unwrap();
+      files.push(path);
+    }
+    files.insert(3, root.join("missing.txt"));
+
+    let search = |jobs: usize, mode: OutputMode| {
+      let mut printer = Printer::new(true, true, 1, 0);
+      printer.mode = mode;
+      let mut out = Vec::new();
+      let unreadable = search_files(&Matcher::sensitive("hit"), None, &mut printer, &files, jobs, ReadOptions::default(), &mut out).unwrap();
+      (String::from_utf8(out).unwrap(), printer.summary(), unreadable)
+    };
+    let one = search(1, OutputMode::Lines);
+    let many = search(8, OutputMode::Lines);
+    let json = (search(1, OutputMode::Json), search(8, OutputMode::Json));
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(one, many);
+    assert_eq!(json.0, json.1);
+    assert_eq!((20, 20, 40, 1), (one.1.files_searched, one.1.files_matched, one.1.matched_lines, one.2));
+    //the "--" between files is still there when the files were searched by different workers
+    let first = one.0.lines().position(|line| line.contains("01.txt")).unwrap();
+    assert_eq!("--", one.0.lines().nth(first - 1).unwrap());
+  }
+
+  #[test]
+  fn whole_file_scan_agrees_with_the_line_by_line_search() {
+    let root = std::env::temp_dir().join(format!("minigrep-mmap-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    //big enough to be memory-mapped, with "\r\n" endings, invalid UTF-8 and a last line without '\n'
+    let mut text = "filler line\n".repeat(10_000).into_bytes();
+    text.extend_from_slice(b"a hit\r\nhits aren't words\n\xff hit \xfe\n");
+    text.extend_from_slice("filler line\n".repeat(10_000).as_bytes());
+    text.extend_from_slice(b"the last hit");
+    let big = root.join("big.txt");
+    fs::write(&big, &text).unwrap();
+    let binary = root.join("binary.txt");
+    fs::write(&binary, [&b"x\n".repeat(10_000)[..], b"\0\nhit\n"].concat()).unwrap();
+    let files = [big, binary];
+
+    let search = |matcher: &Matcher, mmap: bool, mode: OutputMode| {
+      let mut printer = Printer::new(true, true, 0, 0);
+      printer.mode = mode;
+      let mut out = Vec::new();
+      search_files(matcher, None, &mut printer, &files, 1, ReadOptions { mmap, decompress: false }, &mut out).unwrap();
+      String::from_utf8(out).unwrap()
+    };
+    //a regex has no literal to scan for, so it takes the line by line path
+    let word = |matcher: Matcher| matcher.with_boundary(crate::Boundary::Word);
+    let slow = Matcher::regex(crate::regex::Regex::build("hit", false).unwrap());
+    for mode in [OutputMode::Lines, OutputMode::Json, OutputMode::Count] {
+      let expected = search(&slow, true, mode);
+      assert_eq!(expected, search(&Matcher::sensitive("hit"), true, mode));
+      assert_eq!(expected, search(&Matcher::sensitive("hit"), false, mode));
+      assert_eq!(search(&word(slow.clone()), true, mode), search(&word(Matcher::sensitive("hit")), true, mode));
+    }
+    let lines = search(&Matcher::sensitive("hit"), true, OutputMode::Lines);
+    fs::remove_dir_all(&root).unwrap();
+    assert!(lines.contains(":10001:a hit\n") && lines.contains(":10003:\u{FFFD} hit \u{FFFD}\n") && lines.contains(":20004:the last hit\n"));
+    assert!(lines.ends_with("binary.txt matches\n"));
+  }
+
+  #[test]
+  fn compressed_files_are_searched_decompressed_with_z() {
+    use std::io::Write as _;
+    let root = std::env::temp_dir().join(format!("minigrep-zip-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let poem = include_str!("../poem.txt");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(poem.as_bytes()).unwrap();
+    //no .gz on the name, it's the magic bytes that count
+    let files = [root.join("poem.txt"), root.join("poem.1")];
+    fs::write(&files[0], poem).unwrap();
+    fs::write(&files[1], encoder.finish().unwrap()).unwrap();
+
+    let search = |matcher: &Matcher, decompress: bool| {
+      let mut printer = Printer::new(false, true, 0, 0);
+      let mut out = Vec::new();
+      search_files(matcher, None, &mut printer, &files, 1, ReadOptions { mmap: true, decompress }, &mut out).unwrap();
+      String::from_utf8(out).unwrap()
+    };
+    let literal = Matcher::sensitive("nobody");
+    let regex = Matcher::regex(crate::regex::Regex::build("nobody", false).unwrap());
+    let decompressed = search(&literal, true);
+    let with_regex = search(&regex, true);
+    let as_it_is = search(&literal, false);
+    fs::remove_dir_all(&root).unwrap();
+
+    //the same lines from both files, whether the literal fast path or a regex was used
+    assert_eq!("1:I'm nobody! Who are you?\n2:Are you nobody, too?\n".repeat(2), decompressed);
+    assert_eq!(decompressed, with_regex);
+    assert_eq!("1:I'm nobody! Who are you?\n2:Are you nobody, too?\n", as_it_is);
+  }
+
+  #[test]
+  fn stats_count_what_each_way_of_reading_went_through() {
+    let path = std::env::temp_dir().join(format!("minigrep-stats-{}.txt", std::process::id()));
+    let poem = include_str!("../poem.txt");
+    fs::write(&path, poem).unwrap();
+    let summary = |matcher: &Matcher, context: usize, color: bool| {
+      let mut printer = Printer::new(false, false, context, 0);
+      printer.counting = true;
+      printer.color = color;
+      search_files(matcher, None, &mut printer, std::slice::from_ref(&path), 1, ReadOptions::default(), &mut Vec::new()).unwrap();
+      let summary = printer.summary();
+      (summary.bytes_read, summary.lines_examined, summary.matches)
+    };
+    let literal = Matcher::sensitive("o");
+    let multiline = Matcher::regex(crate::regex::Regex::build("o", false).unwrap()).multiline(true);
+    //every line, whether memmem, the matcher or the -U search went through it, and every match in
+    //those lines, whether colours needed them or not
+    let counts = [summary(&literal, 0, false), summary(&literal, 0, true), summary(&literal, 1, false), summary(&multiline, 0, false)];
+    fs::remove_file(&path).unwrap();
+    let bytes = poem.len() as u64;
+    let matches = poem.matches('o').count();
+    assert_eq!([(bytes, 9, matches); 4], counts);
+  }
+
+  #[test]
+  fn multiline_matches_print_their_lines_with_context() {
+    let root = std::env::temp_dir().join(format!("minigrep-multiline-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let path = root.join("lib.rs");
+    fs::write(&path, "use std::fs;\n\npub fn search<'a>(\n  query: &str,\n) -> Vec<&'a str> {\n  Vec::new()\n}\n").unwrap();
+
+    let search = |before: usize, replacement: Option<&Replacement>| {
+      let mut printer = Printer::new(false, true, before, 1);
+      let matcher = Matcher::regex(crate::regex::Regex::build(r"fn (\w+)[^(]*\([^)]*\)", false).unwrap()).multiline(true);
+      let mut out = Vec::new();
+      search_files(&matcher, replacement, &mut printer, std::slice::from_ref(&path), 1, ReadOptions::default(), &mut out).unwrap();
+      (String::from_utf8(out).unwrap(), printer.summary().matched_lines)
+    };
+    let (context, lines) = search(1, None);
+    let (replaced, _) = search(0, Some(&Replacement::new("fn $1()", true)));
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!("2-\n3:pub fn search<'a>(\n4:  query: &str,\n5:) -> Vec<&'a str> {\n6-  Vec::new()\n", context);
+    assert_eq!(3, lines);
+    //the block is one line once it is replaced, the line after it is still the sixth
+    assert_eq!("3:pub fn search() -> Vec<&'a str> {\n6-  Vec::new()\n", replaced);
+  }
+
+  #[test]
+  fn binary_files_only_say_they_match() {
+    let root = std::env::temp_dir().join(format!("minigrep-binary-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let early = root.join("early.bin");
+    let late = root.join("late.bin");
+    fs::write(&early, b"\x7fELF\0\0\nhit\nhit\n").unwrap();
+    fs::write(&late, [&b"hit in text\n"[..], &b"x".repeat(10_000), b"\nhit\0\nhit\n"].concat()).unwrap();
+
+    let search = |text: bool| {
+      let mut printer = Printer::new(true, false, 0, 0);
+      printer.text = text;
+      let mut out = Vec::new();
+      search_files(&Matcher::sensitive("hit"), None, &mut printer, &[early.clone(), late.clone()], 1, ReadOptions::default(), &mut out).unwrap();
+      String::from_utf8(out).unwrap()
+    };
+    let binary = search(false);
+    let text = search(true);
+    fs::remove_dir_all(&root).unwrap();
+
+    let (early, late) = (early.display(), late.display());
+    assert_eq!(format!("Binary file {early} matches\n{late}:hit in text\nBinary file {late} matches\n"), binary);
+    assert_eq!(5, text.lines().count());
+  }
+}
@@ -0,0 +1,1080 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+
+use crate::casefold::fold_char;
+use crate::Boundary;
+
+// A small regular expression engine for the -E/--regex mode.
+// The pattern is parsed into a syntax tree, compiled into a list of instructions for a tiny
+// virtual machine, and executed by a bounded backtracker: every (instruction, position) pair is
+// visited at most once, so matching stays linear in the size of the input even for patterns
+// like (a*)* that would explode a naive backtracker. When the text is too long for the visited
+// pairs to fit in an array, the same program runs on a Pike VM instead, which steps every
+// thread of the program over the text together, one character at a time.
+//
+// Supported syntax: literals, `.`, `^`, `$`, `[...]`/`[^...]` classes with ranges,
+// `\d \w \s \D \W \S`, `\b \B`, groups `(...)` and `(?:...)`, alternation `|`,
+// and the repetitions `*`, `+`, `?`, `{m}`, `{m,}`, `{m,n}` (append `?` for the lazy version).
+
+// upper bound for {m,n} so a typo like a{99999} can't blow up the compiled program
+const MAX_REPEAT: u32 = 1000;
+
+// every copy of a repeated node is compiled, so nested repeats multiply: ((a{1000}){1000}){1000}
+// stays under MAX_REPEAT at every level and would still be a billion instructions. Past this many,
+// the pattern is refused instead
+const MAX_PROGRAM: u64 = 100_000;
+
+// up to this many (instruction, position) pairs the backtracker keeps the visited set in an array;
+// past it, which only happens when a whole file is searched at once (-U), the search goes to the
+// Pike VM, whose memory depends on the program and not on the text
+const DENSE_VISITED: usize = 1 << 20;
+
+#[derive(Debug, PartialEq)]
+pub struct RegexError {
+  pub pattern: String,
+  pub position: usize, //byte offset in the pattern where parsing failed
+  pub message: &'static str,
+}
+
+impl fmt::Display for RegexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "invalid regex {:?} at position {}: {}", self.pattern, self.position, self.message)
+  }
+}
+
+impl Error for RegexError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+  Digit,
+  Word,
+  Space,
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+  Range(char, char),
+  Perl(Perl, bool), //the bool is true for the negated forms \D, \W and \S
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+  items: Vec<ClassItem>,
+  negated: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Look {
+  Start,
+  End,
+  WordBoundary,
+  NotWordBoundary,
+  //no syntax of their own, they wrap the whole pattern for -w
+  NoWordBefore,
+  NoWordAfter,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+  Empty,
+  Literal(char),
+  Any,
+  Class(Class),
+  Look(Look),
+  Group(Box<Node>, Option<usize>), //None for non-capturing groups
+  Concat(Vec<Node>),
+  Alternate(Vec<Node>),
+  Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+impl Node {
+  //how many instructions Compiler::compile writes out for the node
+  fn program_size(&self) -> u64 {
+    match self {
+      Node::Empty => 0,
+      Node::Literal(_) | Node::Any | Node::Class(_) | Node::Look(_) => 1,
+      Node::Group(inner, index) => inner.program_size() + if index.is_some() { 2 } else { 0 },
+      Node::Concat(items) => items.iter().map(Node::program_size).sum(),
+      Node::Alternate(branches) => branches.iter().map(|branch| branch.program_size() + 2).sum::<u64>() - 2,
+      Node::Repeat { node, min, max, .. } => {
+        let size = node.program_size();
+        let optional = match max {
+          None => size + 2,
+          Some(max) => u64::from(max - min) * (size + 1),
+        };
+        //saturating: the sizes of the levels below are already capped, but not their product
+        u64::from(*min).saturating_mul(size).saturating_add(optional)
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+  Char(char),
+  Any,
+  Class(Class),
+  Look(Look),
+  Split(usize, usize), //try the first target, backtrack into the second
+  Jmp(usize),
+  Save(usize),
+  Match,
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+  pattern: String,
+  node: Node, //kept so the same pattern can be compiled again with a different boundary
+  groups: usize,
+  prog: Vec<Inst>,
+  slots: usize,
+  ignore_case: bool,
+  multiline: bool, //-U: `^` and `$` also match at the start and end of every line in the text
+}
+
+impl Regex {
+  // mirrors Config::build: the pattern is validated up front and the error says where it went wrong
+  pub fn build(pattern: &str, ignore_case: bool) -> Result<Regex, RegexError> {
+    Regex::build_many(&[pattern.to_string()], ignore_case)
+  }
+
+  //several patterns (-e/-f) in one program, as if they had been joined with '|';
+  //each one is parsed on its own so an error points into the pattern that caused it
+  pub fn build_many(patterns: &[String], ignore_case: bool) -> Result<Regex, RegexError> {
+    let mut groups = 0;
+    let mut branches = Vec::new();
+    let mut size = 0;
+    for pattern in patterns {
+      //capture groups keep counting up across the patterns
+      let mut parser = Parser { pattern, chars: pattern.char_indices().collect(), pos: 0, groups };
+      let node = parser.parse_alternation()?;
+      if parser.pos < parser.chars.len() {
+        // parse_alternation only stops early on a ')' that has no matching '('
+        return Err(parser.error("unmatched closing parenthesis"));
+      }
+      //parse_repeat catches a single repeat that's too big, this is for the ones that are only too
+      //big together: one after the other, in alternatives, or in several patterns
+      size += node.program_size() + 2;
+      if size > MAX_PROGRAM {
+        parser.pos = 0;
+        return Err(parser.error("pattern is too large"));
+      }
+      groups = parser.groups;
+      branches.push(node);
+    }
+
+    let node = match branches.len() {
+      //no patterns at all (an empty -f file) matches nothing, like grep: an empty class never matches
+      0 => Node::Class(Class { items: Vec::new(), negated: false }),
+      1 => branches.pop().unwrap(),
+      _ => Node::Alternate(branches),
+    };
+    let mut re = Regex { pattern: patterns.join("|"), node, groups, prog: Vec::new(), slots: 0, ignore_case, multiline: false };
+    re.compile(Boundary::Anywhere);
+    Ok(re)
+  }
+
+  //the same pattern, but a match must also be a whole word (-w) or the whole line (-x)
+  pub fn with_boundary(&self, boundary: Boundary) -> Regex {
+    let mut re = self.clone();
+    re.compile(boundary);
+    re
+  }
+
+  //the same pattern for text that has several lines in it (-U), where `^`, `$` and -x go by lines
+  pub fn with_multiline(&self, multiline: bool) -> Regex {
+    Regex { multiline, ..self.clone() }
+  }
+
+  fn compile(&mut self, boundary: Boundary) {
+    let (before, after) = match boundary {
+      Boundary::Anywhere => (None, None),
+      //unlike \b, these also accept a match that starts or ends with a non-word character
+      Boundary::Word => (Some(Look::NoWordBefore), Some(Look::NoWordAfter)),
+      Boundary::Line => (Some(Look::Start), Some(Look::End)),
+    };
+
+    let mut compiler = Compiler { prog: vec![Inst::Save(0)] };
+    compiler.prog.extend(before.map(Inst::Look));
+    compiler.compile(&self.node);
+    compiler.prog.extend(after.map(Inst::Look));
+    compiler.prog.push(Inst::Save(1));
+    compiler.prog.push(Inst::Match);
+
+    self.prog = compiler.prog;
+    self.slots = 2 * (self.groups + 1); //a start and an end slot for the whole match plus each group
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.pattern
+  }
+
+  pub fn is_match(&self, text: &str) -> bool {
+    self.find(text).is_some()
+  }
+
+  // byte range of the leftmost match
+  pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+    self.find_at(text, 0)
+  }
+
+  // leftmost match starting at or after `start`; `^` and `\b` still look at the text before `start`
+  pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+    let slots = self.exec(text, start, 2)?;
+    Some((slots[0]?, slots[1]?))
+  }
+
+  // the leftmost match at or after `start` as a list of spans: the whole match first, then each
+  // capture group in the order of its '('; None for a group that didn't take part in the match
+  pub fn captures_at(&self, text: &str, start: usize) -> Option<Vec<Option<(usize, usize)>>> {
+    let slots = self.exec(text, start, self.slots)?;
+    Some(slots.chunks(2).map(|pair| Some((pair[0]?, pair[1]?))).collect())
+  }
+
+  //`wanted` is how many slots the caller reads: 2 for the match alone, self.slots for the groups too
+  fn exec(&self, text: &str, start: usize, wanted: usize) -> Option<Vec<Option<usize>>> {
+    match self.prog.len().checked_mul(text.len() - start + 1) {
+      Some(states) if states <= DENSE_VISITED => {}
+      _ => return self.pike(text, start, wanted),
+    }
+    SCRATCH.with(|scratch| {
+      let Scratch { seen, set, stack } = &mut *scratch.borrow_mut();
+      // the visited set is shared by every start position: a (pc, pos) state that failed once
+      // fails again no matter where the attempt began
+      let mut visited = Visited::new(seen, set, self.prog.len(), start, text.len());
+      let mut slots = vec![None; self.slots];
+
+      let mut at = start;
+      loop {
+        if self.backtrack(text, at, &mut visited, stack, &mut slots) {
+          return Some(slots);
+        }
+        match text[at..].chars().next() {
+          Some(c) => at += c.len_utf8(),
+          None => return None,
+        }
+      }
+    })
+  }
+
+  fn backtrack(&self, text: &str, start: usize, visited: &mut Visited, stack: &mut Vec<Job>, slots: &mut [Option<usize>]) -> bool {
+    stack.clear();
+    stack.push(Job::Explore(0, start));
+
+    while let Some(job) = stack.pop() {
+      let (mut pc, mut pos) = match job {
+        Job::Explore(pc, pos) => (pc, pos),
+        Job::Restore(slot, old) => {
+          slots[slot] = old;
+          continue;
+        }
+      };
+
+      loop {
+        if !visited.insert(pc, pos) {
+          break;
+        }
+
+        match &self.prog[pc] {
+          Inst::Char(expected) => match text[pos..].chars().next() {
+            Some(c) if chars_eq(c, *expected, self.ignore_case) => {
+              pc += 1;
+              pos += c.len_utf8();
+            }
+            _ => break,
+          },
+          Inst::Any => match text[pos..].chars().next() {
+            Some(c) if c != '\n' => {
+              pc += 1;
+              pos += c.len_utf8();
+            }
+            _ => break,
+          },
+          Inst::Class(class) => match text[pos..].chars().next() {
+            Some(c) if class.matches(c, self.ignore_case) => {
+              pc += 1;
+              pos += c.len_utf8();
+            }
+            _ => break,
+          },
+          Inst::Look(look) => {
+            if look.holds(text, pos, self.multiline) {
+              pc += 1;
+            } else {
+              break;
+            }
+          }
+          Inst::Split(first, second) => {
+            stack.push(Job::Explore(*second, pos));
+            pc = *first;
+          }
+          Inst::Jmp(target) => pc = *target,
+          Inst::Save(slot) => {
+            stack.push(Job::Restore(*slot, slots[*slot]));
+            slots[*slot] = Some(pos);
+            pc += 1;
+          }
+          Inst::Match => return true,
+        }
+      }
+    }
+    false
+  }
+
+  //the threads are kept in priority order, the order the backtracker would try them in, so the
+  //first one to reach Match is the match the backtracker would have found; the ones after it are
+  //dropped, and no new ones start once there's a match. Each thread carries only the slots that
+  //are wanted, so a pattern with many groups doesn't cost them all for every thread when only the
+  //span of the match is asked for
+  fn pike(&self, text: &str, start: usize, wanted: usize) -> Option<Vec<Option<usize>>> {
+    let mut current = Threads::new(self.prog.len(), wanted);
+    let mut next = Threads::new(self.prog.len(), wanted);
+    let mut stack = Vec::new();
+    let mut slots = vec![None; wanted];
+    let mut matched = None;
+
+    let mut pos = start;
+    loop {
+      if matched.is_none() {
+        //a match starting here comes after every match starting further left
+        slots.fill(None);
+        self.add_thread(&mut current, &mut stack, 0, text, pos, &mut slots);
+      }
+      if current.pcs.is_empty() {
+        return matched;
+      }
+
+      let c = text[pos..].chars().next();
+      for (i, &pc) in current.pcs.iter().enumerate() {
+        let steps = match &self.prog[pc] {
+          Inst::Char(expected) => c.is_some_and(|c| chars_eq(c, *expected, self.ignore_case)),
+          Inst::Any => c.is_some_and(|c| c != '\n'),
+          Inst::Class(class) => c.is_some_and(|c| class.matches(c, self.ignore_case)),
+          Inst::Match => {
+            matched = Some(current.slots(i).to_vec());
+            break;
+          }
+          //the rest were followed by add_thread already
+          _ => false,
+        };
+        if let (true, Some(c)) = (steps, c) {
+          slots.copy_from_slice(current.slots(i));
+          self.add_thread(&mut next, &mut stack, pc + 1, text, pos + c.len_utf8(), &mut slots);
+        }
+      }
+
+      match c {
+        Some(c) => pos += c.len_utf8(),
+        None => return matched,
+      }
+      std::mem::swap(&mut current, &mut next);
+      next.clear();
+    }
+  }
+
+  //adds the thread at `pc` to the list, following jumps, splits (first branch first), saves and
+  //assertions at `pos` until every branch stops at an instruction that reads a character or at
+  //Match; a pc that's in the list already is taken by a thread of higher priority
+  fn add_thread(&self, list: &mut Threads, stack: &mut Vec<Job>, pc: usize, text: &str, pos: usize, slots: &mut [Option<usize>]) {
+    stack.push(Job::Explore(pc, pos));
+
+    while let Some(job) = stack.pop() {
+      let mut pc = match job {
+        Job::Explore(pc, _) => pc,
+        Job::Restore(slot, old) => {
+          slots[slot] = old;
+          continue;
+        }
+      };
+
+      loop {
+        if !list.insert(pc) {
+          break;
+        }
+
+        match &self.prog[pc] {
+          Inst::Look(look) => {
+            if look.holds(text, pos, self.multiline) {
+              pc += 1;
+            } else {
+              break;
+            }
+          }
+          Inst::Split(first, second) => {
+            stack.push(Job::Explore(*second, pos));
+            pc = *first;
+          }
+          Inst::Jmp(target) => pc = *target,
+          Inst::Save(slot) => {
+            if *slot < slots.len() {
+              stack.push(Job::Restore(*slot, slots[*slot]));
+              slots[*slot] = Some(pos);
+            }
+            pc += 1;
+          }
+          _ => {
+            list.slots_mut(pc).copy_from_slice(slots);
+            break;
+          }
+        }
+      }
+    }
+  }
+}
+
+//the Pike VM's threads at one position: a sparse set of program counters, in the order they were
+//added, and the capture slots of each, in the same order; the slots only grow as far as the
+//threads that were actually added need
+struct Threads {
+  index: Vec<usize>, //where each pc is in `pcs`, if it's there
+  pcs: Vec<usize>,
+  slots: Vec<Option<usize>>,
+  width: usize, //slots per thread
+}
+
+impl Threads {
+  fn new(instructions: usize, width: usize) -> Threads {
+    Threads { index: vec![0; instructions], pcs: Vec::new(), slots: Vec::new(), width }
+  }
+
+  //false if the pc is in the list already
+  fn insert(&mut self, pc: usize) -> bool {
+    let i = self.index[pc];
+    if i < self.pcs.len() && self.pcs[i] == pc {
+      return false;
+    }
+    self.index[pc] = self.pcs.len();
+    self.pcs.push(pc);
+    if self.slots.len() < self.pcs.len() * self.width {
+      self.slots.resize(self.pcs.len() * self.width, None);
+    }
+    true
+  }
+
+  fn clear(&mut self) {
+    self.pcs.clear();
+  }
+
+  //the slots of the i-th thread
+  fn slots(&self, i: usize) -> &[Option<usize>] {
+    &self.slots[i * self.width..(i + 1) * self.width]
+  }
+
+  fn slots_mut(&mut self, pc: usize) -> &mut [Option<usize>] {
+    let i = self.index[pc];
+    &mut self.slots[i * self.width..(i + 1) * self.width]
+  }
+}
+
+enum Job {
+  Explore(usize, usize),
+  Restore(usize, Option<usize>),
+}
+
+//what a search needs besides the text, kept from one search to the next on each thread:
+//Matcher::find_all calls find_at once per match, and an array the size of the rest of the line
+//allocated every time made a long line with many matches quadratic
+#[derive(Default)]
+struct Scratch {
+  seen: Vec<bool>, //all false between searches
+  set: Vec<usize>, //the entries of `seen` the search going on has set
+  stack: Vec<Job>,
+}
+
+thread_local! {
+  static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::default());
+}
+
+//the (instruction, position) pairs the backtracker has been to, positions counted from where the
+//search started
+struct Visited<'s> {
+  seen: &'s mut Vec<bool>,
+  set: &'s mut Vec<usize>,
+  start: usize,
+  width: usize,
+}
+
+impl<'s> Visited<'s> {
+  //exec only comes here when instructions * width fits in DENSE_VISITED
+  fn new(seen: &'s mut Vec<bool>, set: &'s mut Vec<usize>, instructions: usize, start: usize, len: usize) -> Visited<'s> {
+    let width = len - start + 1;
+    if seen.len() < instructions * width {
+      seen.resize(instructions * width, false);
+    }
+    Visited { seen, set, start, width }
+  }
+
+  //false if the pair was visited already
+  fn insert(&mut self, pc: usize, pos: usize) -> bool {
+    let i = pc * self.width + pos - self.start;
+    if self.seen[i] {
+      return false;
+    }
+    self.seen[i] = true;
+    self.set.push(i);
+    true
+  }
+}
+
+//only what was set is cleared, so the cost of a search is the states it went through, not the
+//size of the array
+impl Drop for Visited<'_> {
+  fn drop(&mut self) {
+    for &i in self.set.iter() {
+      self.seen[i] = false;
+    }
+    self.set.clear();
+  }
+}
+
+fn chars_eq(a: char, b: char, ignore_case: bool) -> bool {
+  a == b || (ignore_case && fold_char(a) == fold_char(b))
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+impl Perl {
+  fn matches(self, c: char) -> bool {
+    match self {
+      Perl::Digit => c.is_ascii_digit(),
+      Perl::Word => is_word_char(c),
+      Perl::Space => c.is_whitespace(),
+    }
+  }
+}
+
+impl Class {
+  fn matches(&self, c: char, ignore_case: bool) -> bool {
+    let hit = |c: char| {
+      self.items.iter().any(|item| match *item {
+        ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+        ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+      })
+    };
+    let mut found = hit(c);
+    if !found && ignore_case {
+      // try the other case of the character too, so [a-z] also accepts 'Q' (and its folding, so [σ] accepts 'ς')
+      found = c.to_lowercase().chain(c.to_uppercase()).chain(std::iter::once(fold_char(c))).any(hit);
+    }
+    found != self.negated
+  }
+}
+
+impl Look {
+  fn holds(self, text: &str, pos: usize, multiline: bool) -> bool {
+    match self {
+      Look::Start => pos == 0 || (multiline && text[..pos].ends_with('\n')),
+      Look::End => pos == text.len() || (multiline && (text[pos..].starts_with('\n') || text[pos..].starts_with("\r\n"))),
+      Look::WordBoundary | Look::NotWordBoundary => {
+        let before = text[..pos].chars().next_back().is_some_and(is_word_char);
+        let after = text[pos..].chars().next().is_some_and(is_word_char);
+        (before != after) == matches!(self, Look::WordBoundary)
+      }
+      Look::NoWordBefore => !text[..pos].chars().next_back().is_some_and(is_word_char),
+      Look::NoWordAfter => !text[pos..].chars().next().is_some_and(is_word_char),
+    }
+  }
+}
+
+// \d, \w, \s and their negated uppercase forms
+fn perl_escape(c: char) -> Option<(Perl, bool)> {
+  match c {
+    'd' => Some((Perl::Digit, false)),
+    'D' => Some((Perl::Digit, true)),
+    'w' => Some((Perl::Word, false)),
+    'W' => Some((Perl::Word, true)),
+    's' => Some((Perl::Space, false)),
+    'S' => Some((Perl::Space, true)),
+    _ => None,
+  }
+}
+
+struct Parser<'p> {
+  pattern: &'p str,
+  chars: Vec<(usize, char)>,
+  pos: usize,
+  groups: usize,
+}
+
+impl Parser<'_> {
+  fn error(&self, message: &'static str) -> RegexError {
+    let position = self.chars.get(self.pos).map_or(self.pattern.len(), |&(i, _)| i);
+    RegexError { pattern: self.pattern.to_string(), position, message }
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.chars.get(self.pos).map(|&(_, c)| c)
+  }
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.peek();
+    if c.is_some() {
+      self.pos += 1;
+    }
+    c
+  }
+
+  fn eat(&mut self, expected: char) -> bool {
+    if self.peek() == Some(expected) {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn parse_alternation(&mut self) -> Result<Node, RegexError> {
+    let mut branches = vec![self.parse_concat()?];
+    while self.eat('|') {
+      branches.push(self.parse_concat()?);
+    }
+    Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Node::Alternate(branches) })
+  }
+
+  fn parse_concat(&mut self) -> Result<Node, RegexError> {
+    let mut items = Vec::new();
+    while let Some(c) = self.peek() {
+      if c == '|' || c == ')' {
+        break;
+      }
+      items.push(self.parse_repeat()?);
+    }
+    Ok(match items.len() {
+      0 => Node::Empty,
+      1 => items.pop().unwrap(),
+      _ => Node::Concat(items),
+    })
+  }
+
+  fn parse_repeat(&mut self) -> Result<Node, RegexError> {
+    let mut node = self.parse_atom()?;
+    loop {
+      let at = self.pos;
+      let (min, max) = match self.peek() {
+        Some('*') => self.operator((0, None)),
+        Some('+') => self.operator((1, None)),
+        Some('?') => self.operator((0, Some(1))),
+        Some('{') => match self.counted_repeat()? {
+          Some(bounds) => bounds,
+          None => break, //a '{' that doesn't start a valid count is matched literally
+        },
+        _ => break,
+      };
+      let greedy = !self.eat('?');
+      node = Node::Repeat { node: Box::new(node), min, max, greedy };
+      if node.program_size() > MAX_PROGRAM {
+        self.pos = at;
+        return Err(self.error("repetition count is too large"));
+      }
+    }
+    Ok(node)
+  }
+
+  fn operator(&mut self, bounds: (u32, Option<u32>)) -> (u32, Option<u32>) {
+    self.pos += 1;
+    bounds
+  }
+
+  // parses {m}, {m,} or {m,n}; on anything else the position is left on the '{' and None returned
+  fn counted_repeat(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+    let start = self.pos;
+    self.pos += 1;
+    let min = self.number();
+    let max = if self.eat(',') { self.number() } else { min };
+
+    match min {
+      Some(min) if self.eat('}') => {
+        if max.is_some_and(|max| max < min) {
+          return Err(self.error("repetition range is reversed"));
+        }
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+          return Err(self.error("repetition count is too large"));
+        }
+        Ok(Some((min, max)))
+      }
+      _ => {
+        self.pos = start;
+        Ok(None)
+      }
+    }
+  }
+
+  fn number(&mut self) -> Option<u32> {
+    let start = self.pos;
+    let mut value: u32 = 0;
+    while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+      value = value.saturating_mul(10).saturating_add(d);
+      self.pos += 1;
+    }
+    (self.pos > start).then_some(value)
+  }
+
+  fn parse_atom(&mut self) -> Result<Node, RegexError> {
+    let c = match self.peek() {
+      Some(c) => c,
+      None => return Ok(Node::Empty),
+    };
+    match c {
+      '*' | '+' | '?' => Err(self.error("repetition operator without anything to repeat")),
+      '(' => {
+        self.pos += 1;
+        let index = if self.peek() == Some('?') {
+          self.pos += 1;
+          if !self.eat(':') {
+            return Err(self.error("only (?:...) groups are supported"));
+          }
+          None
+        } else {
+          self.groups += 1;
+          Some(self.groups)
+        };
+        let inner = self.parse_alternation()?;
+        if !self.eat(')') {
+          return Err(self.error("unclosed group"));
+        }
+        Ok(Node::Group(Box::new(inner), index))
+      }
+      '.' => {
+        self.pos += 1;
+        Ok(Node::Any)
+      }
+      '^' => {
+        self.pos += 1;
+        Ok(Node::Look(Look::Start))
+      }
+      '$' => {
+        self.pos += 1;
+        Ok(Node::Look(Look::End))
+      }
+      '[' => {
+        self.pos += 1;
+        self.parse_class()
+      }
+      '\\' => {
+        self.pos += 1;
+        self.parse_escape()
+      }
+      _ => {
+        self.pos += 1;
+        Ok(Node::Literal(c))
+      }
+    }
+  }
+
+  fn parse_escape(&mut self) -> Result<Node, RegexError> {
+    let c = self.next().ok_or_else(|| self.error("pattern ends with a backslash"))?;
+    if let Some((perl, negated)) = perl_escape(c) {
+      return Ok(Node::Class(Class { items: vec![ClassItem::Perl(perl, negated)], negated: false }));
+    }
+    Ok(match c {
+      'b' => Node::Look(Look::WordBoundary),
+      'B' => Node::Look(Look::NotWordBoundary),
+      'n' => Node::Literal('\n'),
+      't' => Node::Literal('\t'),
+      'r' => Node::Literal('\r'),
+      '1'..='9' => {
+        self.pos -= 1;
+        return Err(self.error("backreferences are not supported"));
+      }
+      c if c.is_alphanumeric() => {
+        self.pos -= 1;
+        return Err(self.error("unknown escape sequence"));
+      }
+      c => Node::Literal(c), //escaped punctuation such as \. or \* is matched literally
+    })
+  }
+
+  fn parse_class(&mut self) -> Result<Node, RegexError> {
+    let negated = self.eat('^');
+    let mut items = Vec::new();
+    let mut first = true;
+
+    loop {
+      let c = match self.next() {
+        Some(c) => c,
+        None => return Err(self.error("unclosed character class")),
+      };
+      if c == ']' && !first {
+        break;
+      }
+      first = false;
+
+      let lo = if c == '\\' {
+        let escaped = self.next().ok_or_else(|| self.error("unclosed character class"))?;
+        if let Some((perl, negated)) = perl_escape(escaped) {
+          items.push(ClassItem::Perl(perl, negated));
+          continue;
+        }
+        match escaped {
+          'n' => '\n',
+          't' => '\t',
+          'r' => '\r',
+          c => c,
+        }
+      } else {
+        c
+      };
+
+      // a '-' right before the closing ']' is a literal dash, not a range
+      let is_range = self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&(_, c)| c != ']');
+      if is_range {
+        self.pos += 1;
+        let hi = match self.next() {
+          Some('\\') => self.next().ok_or_else(|| self.error("unclosed character class"))?,
+          Some(c) => c,
+          None => return Err(self.error("unclosed character class")),
+        };
+        if hi < lo {
+          self.pos -= 1;
+          return Err(self.error("character class range is reversed"));
+        }
+        items.push(ClassItem::Range(lo, hi));
+      } else {
+        items.push(ClassItem::Range(lo, lo));
+      }
+    }
+    Ok(Node::Class(Class { items, negated }))
+  }
+}
+
+struct Compiler {
+  prog: Vec<Inst>,
+}
+
+impl Compiler {
+  fn emit(&mut self, inst: Inst) -> usize {
+    self.prog.push(inst);
+    self.prog.len() - 1
+  }
+
+  fn patch_split_second(&mut self, at: usize, target: usize) {
+    if let Inst::Split(_, second) = &mut self.prog[at] {
+      *second = target;
+    }
+  }
+
+  fn patch_jmp(&mut self, at: usize, target: usize) {
+    if let Inst::Jmp(to) = &mut self.prog[at] {
+      *to = target;
+    }
+  }
+
+  fn compile(&mut self, node: &Node) {
+    match node {
+      Node::Empty => {}
+      Node::Literal(c) => {
+        self.emit(Inst::Char(*c));
+      }
+      Node::Any => {
+        self.emit(Inst::Any);
+      }
+      Node::Class(class) => {
+        self.emit(Inst::Class(class.clone()));
+      }
+      Node::Look(look) => {
+        self.emit(Inst::Look(*look));
+      }
+      Node::Group(inner, index) => match index {
+        Some(i) => {
+          self.emit(Inst::Save(2 * i));
+          self.compile(inner);
+          self.emit(Inst::Save(2 * i + 1));
+        }
+        None => self.compile(inner),
+      },
+      Node::Concat(items) => {
+        for item in items {
+          self.compile(item);
+        }
+      }
+      Node::Alternate(branches) => {
+        let mut jumps = Vec::new();
+        for (i, branch) in branches.iter().enumerate() {
+          if i + 1 == branches.len() {
+            self.compile(branch);
+          } else {
+            let split = self.emit(Inst::Split(self.prog.len() + 1, 0));
+            self.compile(branch);
+            jumps.push(self.emit(Inst::Jmp(0)));
+            let next = self.prog.len();
+            self.patch_split_second(split, next);
+          }
+        }
+        let end = self.prog.len();
+        for jump in jumps {
+          self.patch_jmp(jump, end);
+        }
+      }
+      Node::Repeat { node, min, max, greedy } => {
+        for _ in 0..*min {
+          self.compile(node);
+        }
+        match max {
+          None => {
+            // loop: split into the body or out, then jump back to the split
+            let split = self.emit(Inst::Split(0, 0));
+            self.compile(node);
+            self.emit(Inst::Jmp(split));
+            let end = self.prog.len();
+            self.prog[split] = if *greedy { Inst::Split(split + 1, end) } else { Inst::Split(end, split + 1) };
+          }
+          Some(max) => {
+            // each optional copy may bail out straight to the end
+            let mut splits = Vec::new();
+            for _ in *min..*max {
+              splits.push(self.emit(Inst::Split(0, 0)));
+              self.compile(node);
+            }
+            let end = self.prog.len();
+            for split in splits {
+              self.prog[split] = if *greedy { Inst::Split(split + 1, end) } else { Inst::Split(end, split + 1) };
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+    Regex::build(pattern, false).unwrap().find(text)
+  }
+
+  #[test]
+  fn literals_and_anchors() {
+    assert_eq!(Some((0, 19)), find("^To .* bog!$", "To an admiring bog!"));
+    assert_eq!(None, find("^To .* bog!$", "To tell your name the livelong day"));
+    assert_eq!(Some((4, 8)), find("duct", "pro duct"));
+  }
+
+  #[test]
+  fn classes_repeats_and_alternation() {
+    assert_eq!(Some((3, 6)), find("[0-9]+", "abc123def"));
+    assert_eq!(Some((0, 3)), find("\\w{2,3}", "abcd"));
+    assert_eq!(Some((0, 1)), find("\\w{1,3}?", "abcd"));
+    assert_eq!(Some((4, 7)), find("cat|dog", "hot dog"));
+    assert_eq!(Some((0, 5)), find("(?:ab)*c", "ababc"));
+    assert_eq!(Some((5, 10)), find("\\bthere\\b", "Then there's"));
+    assert_eq!(Some((1, 4)), find("[^a]{3}", "abcd"));
+    assert_eq!(Some((0, 4)), find("a{1}b{2", "ab{2"));
+  }
+
+  #[test]
+  fn ignore_case() {
+    let re = Regex::build("rUsT", true).unwrap();
+    assert!(re.is_match("Trust me."));
+    assert!(Regex::build("[a-c]x", true).unwrap().is_match("Bx"));
+  }
+
+  #[test]
+  fn many_patterns() {
+    let patterns = vec![String::from("^To"), String::from("(b)og!")];
+    let re = Regex::build_many(&patterns, false).unwrap();
+    assert_eq!(Some((0, 2)), re.find("To an admiring bog!"));
+    assert_eq!(Some((10, 14)), re.find("admiring  bog!"));
+    assert_eq!(None, Regex::build_many(&[], false).unwrap().find("anything"));
+
+    let error = Regex::build_many(&[String::from("ok"), String::from("a)")], false).unwrap_err();
+    assert_eq!(("a)", 1), (error.pattern.as_str(), error.position));
+  }
+
+  #[test]
+  fn capture_groups() {
+    let re = Regex::build(r"(\w+)@(\w+)?(x)?", false).unwrap();
+    assert_eq!(Some(vec![Some((5, 15)), Some((5, 9)), Some((10, 15)), None]), re.captures_at("mail noel@crate", 0));
+    assert_eq!(Some(Some((6, 9))), re.captures_at("mail noel@crate", 6).map(|groups| groups[1]));
+    assert_eq!(None, re.captures_at("mail noel", 0));
+  }
+
+  #[test]
+  fn boundaries() {
+    let re = Regex::build("ab|abc", false).unwrap();
+    assert_eq!(Some((0, 3)), re.with_boundary(Boundary::Word).find("abc d"));
+    assert_eq!(None, re.with_boundary(Boundary::Word).find("abcd"));
+    assert_eq!(Some((0, 3)), re.with_boundary(Boundary::Line).find("abc"));
+    assert_eq!(Some((2, 5)), Regex::build("-a-", false).unwrap().with_boundary(Boundary::Word).find("x -a- y"));
+  }
+
+  #[test]
+  fn pathological_patterns_terminate() {
+    let text = "a".repeat(200);
+    assert_eq!(None, find("(a*)*b", &text));
+    //too long for the visited array, so it runs on the Pike VM
+    let text = "a".repeat(120_000);
+    assert_eq!(None, find("(a*)*b", &text));
+  }
+
+  #[test]
+  fn the_pike_vm_finds_what_the_backtracker_finds() {
+    let cases = [
+      ("a+b", "xaaab aab"),
+      ("a*?b", "aaab"),
+      ("(a|ab)(c|bcd)", "abcd"),
+      ("(a*)*b", "aaaa"),
+      ("^(\\w+) (\\w+)$", "hello world\nbye now"),
+      ("o.*\\nz", "frog\nzebra\nfoo\nzoo"),
+      ("\\bon\\b", "onion on"),
+      ("(x)?y|(z)", "zy"),
+      ("[^a-c]{2,3}", "abcdefg"),
+    ];
+    for (pattern, text) in cases {
+      for multiline in [false, true] {
+        let re = Regex::build(pattern, false).unwrap().with_multiline(multiline);
+        for start in 0..=text.len() {
+          assert_eq!(re.exec(text, start, re.slots), re.pike(text, start, re.slots), "{pattern:?} in {text:?} from {start}");
+          assert_eq!(re.find_at(text, start), re.pike(text, start, 2).map(|slots| (slots[0].unwrap(), slots[1].unwrap())));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn searches_on_one_thread_start_from_a_clean_slate() {
+    //the same pattern and text twice: states left marked by the first search would make the
+    //second one give up early
+    let re = Regex::build("a+b", false).unwrap();
+    let text = "aaaaaaaab aab";
+    assert_eq!(Some((0, 9)), re.find(text));
+    assert_eq!(Some((0, 9)), re.find(text));
+    assert_eq!(Some((10, 13)), re.find_at(text, 9));
+    assert_eq!(None, Regex::build("a+c", false).unwrap().find(text));
+    assert_eq!(Some((0, 9)), re.find(text));
+  }
+
+  #[test]
+  fn anchors_by_line_in_multiline_text() {
+    let text = "fn main(\r\n  args: u32,\n) {";
+    let re = Regex::build(r"^\s*args.*$", false).unwrap();
+    assert_eq!(None, re.find(text));
+    assert_eq!(Some((10, 22)), re.with_multiline(true).find(text));
+    assert_eq!(Some((0, 24)), Regex::build(r"fn \w+\(\s*args[^)]*\)", false).unwrap().find(text));
+    let line = Regex::build(r"\) \{", false).unwrap().with_boundary(Boundary::Line);
+    assert_eq!(Some((23, 26)), line.with_multiline(true).find(text));
+  }
+
+  #[test]
+  fn syntax_errors() {
+    assert_eq!("unclosed group", Regex::build("(abc", false).unwrap_err().message);
+    assert_eq!("unmatched closing parenthesis", Regex::build("abc)", false).unwrap_err().message);
+    assert_eq!("unclosed character class", Regex::build("[abc", false).unwrap_err().message);
+    assert_eq!(0, Regex::build("*a", false).unwrap_err().position);
+    //each count is allowed on its own, not the program they'd make together
+    let nested = Regex::build("x((a{1000}){1000}){1000}", false).unwrap_err();
+    assert_eq!(("repetition count is too large", 11), (nested.message, nested.position));
+    assert!(Regex::build("(a{1000}){50}", false).is_ok());
+    //and repeats that are allowed on their own, but not all together
+    let concatenated = Regex::build(&"(a{1000}){99}".repeat(200), false).unwrap_err();
+    assert_eq!(("pattern is too large", 0), (concatenated.message, concatenated.position));
+    assert!(Regex::build(&"(a{1000}){99}|".repeat(2), false).is_err());
+    let patterns = vec![String::from("abc"), "(a{1000}){60}".to_string(), "(a{1000}){60}".to_string()];
+    let many = Regex::build_many(&patterns, false).unwrap_err();
+    assert_eq!(("(a{1000}){60}", 0), (many.pattern.as_str(), many.position));
+  }
+}
@@ -0,0 +1,257 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::error::Error;
+use crate::output::Summary;
+use crate::Matcher;
+
+// --replace TEXT: what every match in a selected line is replaced with.
+// With -E, `$1` (or `${1}`, for when a digit follows) is what the first group of the regex
+// matched, `$0` the whole match and `$$` a plain '$'; a group that didn't take part in the match
+// gives nothing. Literal patterns have no groups, so without -E the text is used as it is.
+// --in-place writes the result back into the files. By default that's only a dry run showing
+// the changes as a unified diff; --apply really writes them, each file through a temporary file
+// that is renamed over the original, so a crash never leaves a file half written.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+  Text(String),
+  Group(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+  pieces: Vec<Piece>,
+}
+
+impl Replacement {
+  pub fn new(text: &str, regex: bool) -> Replacement {
+    if !regex {
+      return Replacement { pieces: vec![Piece::Text(text.to_string())] };
+    }
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(dollar) = rest.find('$') {
+      literal.push_str(&rest[..dollar]);
+      rest = &rest[dollar + 1..];
+      let (group, len) = match rest.strip_prefix('{').and_then(|braced| braced.split_once('}')) {
+        Some((digits, _)) => (digits.parse().ok(), digits.len() + 2),
+        None => {
+          let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+          (rest[..digits].parse().ok(), digits)
+        }
+      };
+      match group {
+        Some(group) => {
+          if !literal.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut literal)));
+          }
+          pieces.push(Piece::Group(group));
+          rest = &rest[len..];
+        }
+        //"$$" is a '$', and so is a '$' that isn't followed by a group number
+        None => {
+          literal.push('$');
+          rest = rest.strip_prefix('$').unwrap_or(rest);
+        }
+      }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+      pieces.push(Piece::Text(literal));
+    }
+    Replacement { pieces }
+  }
+
+  fn uses_groups(&self) -> bool {
+    self.pieces.iter().any(|piece| matches!(piece, Piece::Group(_)))
+  }
+
+  //`line` with each of `spans` (the matches find_all() found in it) replaced, written into `out`;
+  //`new_spans` gets where the replacements ended up in it, for the colours
+  pub fn apply(&self, matcher: &Matcher, line: &str, spans: &[(usize, usize)], out: &mut String, new_spans: &mut Vec<(usize, usize)>) {
+    let mut copied = 0;
+    for &(start, end) in spans {
+      out.push_str(&line[copied..start]);
+      let inserted = out.len();
+      //the groups are only looked for when the text refers to one, that's a second run of the regex
+      let groups = match self.uses_groups() {
+        true => matcher.captures_at(line, start).unwrap_or_default(),
+        false => Vec::new(),
+      };
+      for piece in &self.pieces {
+        match piece {
+          Piece::Text(text) => out.push_str(text),
+          Piece::Group(0) => out.push_str(&line[start..end]),
+          Piece::Group(n) => {
+            if let Some(Some((from, to))) = groups.get(*n) {
+              out.push_str(&line[*from..*to]);
+            }
+          }
+        }
+      }
+      new_spans.push((inserted, out.len()));
+      copied = end;
+    }
+    out.push_str(&line[copied..]);
+  }
+}
+
+const REMOVED_COLOR: &str = "\x1b[31m";
+const ADDED_COLOR: &str = "\x1b[32m";
+const HEADER_COLOR: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+//what --in-place does with each file
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RewriteOptions {
+  pub apply: bool, //--apply: write the files instead of showing the diff
+  pub color: bool,
+  pub text: bool, //-a: rewrite binary files too
+  pub max_count: Option<usize>, //-m: only the first NUM matching lines of each file are changed
+}
+
+//replaces the matches in one file; the Summary counts the file, and the lines and matches that
+//were replaced. A file that can't be read, or isn't text, is a Read error and is left alone;
+//Rewrite means it couldn't be written back, Write that the diff couldn't be printed
+pub fn rewrite_file(matcher: &Matcher, replacement: &Replacement, path: &Path, options: RewriteOptions, out: &mut impl Write) -> Result<Summary, Error> {
+  let bytes = fs::read(path).map_err(Error::read(path))?;
+  let not_text = |why: &str| Error::Read { path: path.to_path_buf(), source: io::Error::new(io::ErrorKind::InvalidData, why.to_string()) };
+  if bytes.contains(&0) && !options.text {
+    return Err(not_text("binary file, not rewritten"));
+  }
+  let contents = String::from_utf8(bytes).map_err(|_| not_text("not valid UTF-8, not rewritten"))?;
+
+  let mut summary = Summary { files_searched: 1, bytes_read: contents.len() as u64, ..Summary::default() };
+  let mut rewritten = String::with_capacity(contents.len());
+  let mut changes = Vec::new(); //(line number, old line, where the new line is in `rewritten`, its "\r" if it has one)
+  let mut spans = Vec::new();
+  let mut new_spans = Vec::new();
+  for (i, line) in contents.split_inclusive('\n').enumerate() {
+    //the matches are looked for in the line without its ending, which is kept as it was ("\r\n" too)
+    let text = line.trim_end_matches('\n').trim_end_matches('\r');
+    summary.lines_examined += 1;
+    spans.clear();
+    if !matcher.is_inverted() && options.max_count.is_none_or(|max| summary.matched_lines < max) {
+      matcher.find_all(text, &mut spans);
+    }
+    if spans.is_empty() {
+      rewritten.push_str(line);
+      continue;
+    }
+    let start = rewritten.len();
+    replacement.apply(matcher, text, &spans, &mut rewritten, &mut new_spans);
+    let end = rewritten.len();
+    rewritten.push_str(&line[text.len()..]);
+    summary.matched_lines += 1;
+    summary.matches += spans.len();
+    if rewritten[start..end] != *text {
+      changes.push((i + 1, text, start..end, line[text.len()..].trim_end_matches('\n')));
+    }
+  }
+  if changes.is_empty() {
+    return Ok(summary);
+  }
+  summary.files_matched = 1;
+
+  if options.apply {
+    write_atomically(path, &rewritten).map_err(|source| Error::Rewrite { path: path.to_path_buf(), source })?;
+    return Ok(summary);
+  }
+  //one hunk per changed line, which is enough for `patch -p0` to apply the preview as it is; the
+  //lines end the way they do in the file, "\r\n" included, so the preview is what will be written
+  let paint = |color: &str, text: String| match options.color {
+    true => format!("{color}{text}{RESET}"),
+    false => text,
+  };
+  let mut print = || -> io::Result<()> {
+    writeln!(out, "{}", paint(HEADER_COLOR, format!("--- {}", path.display())))?;
+    writeln!(out, "{}", paint(HEADER_COLOR, format!("+++ {}", path.display())))?;
+    for (number, old, new, cr) in &changes {
+      writeln!(out, "{}", paint(HEADER_COLOR, format!("@@ -{number} +{number} @@")))?;
+      writeln!(out, "{}{cr}", paint(REMOVED_COLOR, format!("-{old}")))?;
+      writeln!(out, "{}{cr}", paint(ADDED_COLOR, format!("+{}", &rewritten[new.clone()])))?;
+    }
+    Ok(())
+  };
+  print().map_err(Error::Write)?;
+  Ok(summary)
+}
+
+//writes `contents` next to the file and renames it over the file, so anyone reading it sees either
+//the old contents or the new ones; a symlink is followed, so it's the file it points to that changes
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+  let path = fs::canonicalize(path)?;
+  let permissions = fs::metadata(&path)?.permissions();
+  let name = path.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+  let temp = path.with_file_name(format!(".{name}.minigrep-{}", process::id()));
+  let result = write_temp(&temp, contents, permissions).and_then(|()| fs::rename(&temp, &path));
+  if result.is_err() {
+    let _ = fs::remove_file(&temp);
+  }
+  result
+}
+
+fn write_temp(temp: &PathBuf, contents: &str, permissions: fs::Permissions) -> io::Result<()> {
+  let mut file = File::options().write(true).create_new(true).open(temp)?;
+  file.write_all(contents.as_bytes())?;
+  file.set_permissions(permissions)?;
+  //on disk before the rename, or a crash could leave an empty file where the old one was
+  file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::regex::Regex;
+  use crate::Boundary;
+
+  fn replace(matcher: &Matcher, replacement: &Replacement, line: &str) -> (String, Vec<(usize, usize)>) {
+    let mut spans = Vec::new();
+    matcher.find_all(line, &mut spans);
+    let (mut out, mut new_spans) = (String::new(), Vec::new());
+    replacement.apply(matcher, line, &spans, &mut out, &mut new_spans);
+    (out, new_spans)
+  }
+
+  #[test]
+  fn groups_and_literal_text() {
+    let dates = Matcher::regex(Regex::build(r"(\d+)-(\d+)", false).unwrap());
+    let swap = Replacement::new("$2/${1}x $$5 $0 $9$", true);
+    let swapped = replace(&dates, &swap, "on 2024-07 and 3-1");
+    assert_eq!(("on 07/2024x $5 2024-07 $ and 1/3x $5 3-1 $", vec![(3, 24), (29, 42)]), (swapped.0.as_str(), swapped.1));
+
+    let literal = Replacement::new("$1", false);
+    assert_eq!("I'm $1!", replace(&Matcher::insensitive("NOBODY"), &literal, "I'm nobody!").0);
+    let word = Matcher::sensitive("to").with_boundary(Boundary::Word);
+    //-w only replaces the whole word "to", not the "to" in "tomorrow"
+    assert_eq!("a frog tomorrow", replace(&word, &Replacement::new("frog", false), "a to tomorrow").0);
+  }
+
+  #[test]
+  fn previews_and_rewrites_files() {
+    let path = std::env::temp_dir().join(format!("minigrep-replace-{}.txt", std::process::id()));
+    fs::write(&path, "Then there's a pair of us!\r\nDon't tell!\nThey'd banish us, you know.").unwrap();
+    let (matcher, replacement) = (Matcher::regex(Regex::build(r"(\w+)!", false).unwrap()), Replacement::new("$1?", true));
+
+    let mut diff = Vec::new();
+    let preview = rewrite_file(&matcher, &replacement, &path, RewriteOptions::default(), &mut diff).unwrap();
+    let diff = String::from_utf8(diff).unwrap();
+    assert_eq!((1, 2, 2), (preview.files_matched, preview.matched_lines, preview.matches));
+    assert!(diff.contains("@@ -2 +2 @@\n-Don't tell!\n+Don't tell?\n"));
+    assert!(diff.contains("@@ -1 +1 @@\n-Then there's a pair of us!\r\n+Then there's a pair of us?\r\n"));
+    assert!(fs::read_to_string(&path).unwrap().contains("tell!"));
+
+    let options = RewriteOptions { apply: true, max_count: Some(1), ..RewriteOptions::default() };
+    rewrite_file(&matcher, &replacement, &path, options, &mut io::sink()).unwrap();
+    let contents = fs::read_to_string(&path).unwrap();
+    let temp = path.with_file_name(format!(".{}.minigrep-{}", path.file_name().unwrap().to_string_lossy(), std::process::id()));
+    let leftovers = temp.exists();
+    fs::remove_file(&path).unwrap();
+    assert_eq!("Then there's a pair of us?\r\nDon't tell!\nThey'd banish us, you know.", contents);
+    assert!(!leftovers, "the temporary file was left behind");
+  }
+}
@@ -0,0 +1,435 @@
+use crate::buffer::CandidateLines;
+use crate::config::ArgsError;
+use crate::error::Error;
+use crate::matcher::{Boundary, Matcher};
+use crate::multiline;
+use crate::regex::Regex;
+
+//a matching line together with where it sits in the contents and where the query was found in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+  pub line_number: usize, //counted from 1, like grep -n and editors do
+  pub line_offset: usize, //byte offset of the start of the line in the contents
+  pub line: &'a str, //with multiline(true), every line the match is on
+  pub start: usize, //byte column where the match begins in the line
+  pub end: usize, //byte column right after the match
+  pub distance: usize, //edits between the match and the query: 0 unless the search is --fuzzy
+}
+
+impl<'a> Match<'a> {
+  //the part of the line that matched
+  pub fn matched(&self) -> &'a str {
+    &self.line[self.start..self.end]
+  }
+
+  //the line the match ends on, which is only another line than line_number with multiline(true)
+  pub fn end_line_number(&self) -> usize {
+    self.line_number + self.line[..self.end].matches('\n').count()
+  }
+}
+
+//collects the settings of a search, which mirror the command line options, and checks them in build()
+//  let searcher = Searcher::builder().pattern("rust").ignore_case(true).max_count(Some(1)).build()?;
+#[derive(Debug, Clone, Default)]
+pub struct SearcherBuilder {
+  patterns: Vec<String>,
+  ignore_case: bool,
+  regex: bool,
+  boundary: Boundary,
+  invert: bool,
+  max_count: Option<usize>,
+  fuzzy: Option<usize>,
+  multiline: bool,
+}
+
+impl SearcherBuilder {
+  //a line matches if any of the patterns does
+  pub fn pattern(mut self, pattern: &str) -> SearcherBuilder {
+    self.patterns.push(pattern.to_string());
+    self
+  }
+
+  pub fn patterns(mut self, patterns: &[String]) -> SearcherBuilder {
+    self.patterns.extend_from_slice(patterns);
+    self
+  }
+
+  pub fn ignore_case(mut self, ignore_case: bool) -> SearcherBuilder {
+    self.ignore_case = ignore_case;
+    self
+  }
+
+  //treat the patterns as regular expressions instead of literal text
+  pub fn regex(mut self, regex: bool) -> SearcherBuilder {
+    self.regex = regex;
+    self
+  }
+
+  pub fn boundary(mut self, boundary: Boundary) -> SearcherBuilder {
+    self.boundary = boundary;
+    self
+  }
+
+  //select the lines that do NOT match
+  pub fn invert(mut self, invert: bool) -> SearcherBuilder {
+    self.invert = invert;
+    self
+  }
+
+  //stop after this many selected lines (-m); None for no limit
+  pub fn max_count(mut self, max_count: Option<usize>) -> SearcherBuilder {
+    self.max_count = max_count;
+    self
+  }
+
+  //match within this many edits of the pattern (--fuzzy); only for a single literal pattern
+  pub fn fuzzy(mut self, max_distance: Option<usize>) -> SearcherBuilder {
+    self.fuzzy = max_distance;
+    self
+  }
+
+  //let matches go over line breaks (-U); the patterns are matched against the whole text
+  pub fn multiline(mut self, multiline: bool) -> SearcherBuilder {
+    self.multiline = multiline;
+    self
+  }
+
+  //compiles the patterns, which is where an invalid regex is reported
+  pub fn build(self) -> Result<Searcher, Error> {
+    let matcher = match (self.fuzzy, self.patterns.as_slice()) {
+      (Some(max_distance), [query]) if !self.regex => Matcher::fuzzy(query, max_distance, self.ignore_case),
+      (Some(_), _) => {
+        return Err(ArgsError::Requires { option: String::from("--fuzzy"), requirement: "a single literal query, without -E" }.into())
+      }
+      (None, _) if self.regex => Matcher::regex(Regex::build_many(&self.patterns, self.ignore_case)?),
+      (None, _) => Matcher::literals(&self.patterns, self.ignore_case),
+    };
+    //-v selects lines, and a fuzzy match is looked for one line at a time
+    if self.multiline && (self.invert || self.fuzzy.is_some()) {
+      return Err(ArgsError::Requires { option: String::from("--multiline"), requirement: "a search without -v or --fuzzy" }.into());
+    }
+    let matcher = matcher.with_boundary(self.boundary).inverted(self.invert).multiline(self.multiline);
+    Ok(Searcher { matcher, max_count: self.max_count })
+  }
+}
+
+//searches text that is already in memory; nothing is collected, so the first hit is just
+//search(contents).next() and the caller decides whether a Vec is worth allocating
+#[derive(Debug, Clone)]
+pub struct Searcher {
+  matcher: Matcher,
+  max_count: Option<usize>,
+}
+
+impl Searcher {
+  pub fn builder() -> SearcherBuilder {
+    SearcherBuilder::default()
+  }
+
+  //the plain case sensitive search for one literal query
+  pub fn new(query: &str) -> Searcher {
+    Searcher::from(Matcher::sensitive(query))
+  }
+
+  pub fn matcher(&self) -> &Matcher {
+    &self.matcher
+  }
+
+  pub fn max_count(&self) -> Option<usize> {
+    self.max_count
+  }
+
+  //the selected lines of `contents`, in order, each with the position of its first match
+  //(an inverted search selects the lines without a match, those get an empty 0..0 span).
+  //A multiline search gives the blocks of lines its matches are on instead (see multiline.rs)
+  pub fn search<'s, 'a: 's>(&'s self, contents: &'a str) -> impl Iterator<Item = Match<'a>> + 's {
+    let matches: Box<dyn Iterator<Item = Match<'a>> + 's> = match self.matcher.is_multiline() {
+      true => Box::new(multiline::blocks(&self.matcher, contents).map(|block| {
+        let (start, end) = block.spans[0];
+        Match { line_number: block.number, line_offset: block.offset, line: block.text, start, end, distance: 0 }
+      })),
+      false => Box::new(self.search_lines(contents)),
+    };
+    matches.take(self.max_count.unwrap_or(usize::MAX))
+  }
+
+  fn search_lines<'s, 'a: 's>(&'s self, contents: &'a str) -> impl Iterator<Item = Match<'a>> + 's {
+    //with a literal query, the lines without it are skipped over by one scan of the whole text
+    //instead of each being searched on its own (see buffer.rs)
+    let lines: Box<dyn Iterator<Item = (usize, (usize, &'a str))> + 's> = match self.matcher.required_literal() {
+      Some(needle) => Box::new(
+        CandidateLines::new(contents.as_bytes(), needle).map(|line| (line.number - 1, (line.start, &contents[line.start..line.end]))),
+      ),
+      None => Box::new(lines_with_offsets(contents).enumerate()),
+    };
+    lines.filter_map(|(index, (line_offset, line))| {
+      let found = self.matcher.find(line);
+      let (start, end) = found.unwrap_or((0, 0));
+      let distance = self.matcher.distance(&line[start..end]);
+      (found.is_some() != self.matcher.is_inverted()).then_some(Match { line_number: index + 1, line_offset, line, start, end, distance })
+    })
+  }
+}
+
+impl From<Matcher> for Searcher {
+  fn from(matcher: Matcher) -> Searcher {
+    Searcher { matcher, max_count: None }
+  }
+}
+
+//the same lines as contents.lines(), each paired with the byte offset where it starts
+pub(crate) fn lines_with_offsets(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+  contents.split_inclusive('\n').scan(0, |offset, raw| {
+    let start = *offset;
+    *offset += raw.len();
+    let line = raw.strip_suffix('\n').map_or(raw, |line| line.strip_suffix('\r').unwrap_or(line));
+    Some((start, line))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  fn lines<'a>(searcher: &Searcher, contents: &'a str) -> Vec<&'a str> {
+    searcher.search(contents).map(|m| m.line).collect()
+  }
+
+  fn insensitive(query: &str) -> Searcher {
+    Searcher::builder().pattern(query).ignore_case(true).build().unwrap()
+  }
+
+  //the test function describes the desired behavior: given a query and contents, return only lines that contain the query
+  #[test]
+  fn case_sensitive() {
+    let query = "duct";
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+    
+    assert_eq!(vec!["safe, fast, productive."], lines(&Searcher::new(query), contents));
+    
+  }
+  
+  #[test]
+  fn case_insensitive() {
+    let query = "rUsT";
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+    assert_eq!(vec!["Rust:", "Trust me."], lines(&insensitive(query), contents));
+  }
+
+  #[test]
+  fn case_folding() {
+    let contents = "\
+Die Straße
+STRASSE
+ΟΔΥΣΣΕΥΣ
+ὀδυσσεύς
+İstanbul";
+
+    assert_eq!(vec!["Die Straße", "STRASSE"], lines(&insensitive("strasse"), contents));
+    assert_eq!(vec!["ΟΔΥΣΣΕΥΣ"], lines(&insensitive("οδυσσευς"), contents));
+    assert_eq!(Vec::<&str>::new(), lines(&insensitive("istanbul"), contents));
+  }
+  
+  //-v, -w and -x have to work the same whatever the case mode
+  fn selected<'a>(matcher: &Matcher, contents: &'a str) -> Vec<&'a str> {
+    lines(&Searcher::from(matcher.clone()), contents)
+  }
+
+  #[test]
+  fn invert_match() {
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+    assert_eq!(vec!["Rust:", "Pick three.", "Trust me."], selected(&Matcher::sensitive("duct").inverted(true), contents));
+    assert_eq!(vec!["safe, fast, productive.", "Pick three."], selected(&Matcher::insensitive("rUsT").inverted(true), contents));
+  }
+
+  #[test]
+  fn whole_word() {
+    let contents = "\
+Rust:
+Trust me.
+rust-lang";
+
+    assert_eq!(vec!["Rust:"], selected(&Matcher::sensitive("Rust").with_boundary(Boundary::Word), contents));
+    assert_eq!(vec!["Rust:", "rust-lang"], selected(&Matcher::insensitive("RUST").with_boundary(Boundary::Word), contents));
+    assert_eq!(
+      vec!["Trust me."],
+      selected(&Matcher::insensitive("rust").with_boundary(Boundary::Word).inverted(true), contents)
+    );
+    let re = Regex::build("r.st", true).unwrap();
+    assert_eq!(vec!["Rust:", "rust-lang"], selected(&Matcher::regex(re).with_boundary(Boundary::Word), contents));
+  }
+
+  #[test]
+  fn whole_line() {
+    let contents = "\
+Pick three.
+pick three.
+Pick three. Or four.";
+
+    assert_eq!(vec!["Pick three."], selected(&Matcher::sensitive("Pick three.").with_boundary(Boundary::Line), contents));
+    assert_eq!(
+      vec!["Pick three.", "pick three."],
+      selected(&Matcher::insensitive("PICK THREE.").with_boundary(Boundary::Line), contents)
+    );
+    assert_eq!(
+      vec!["Pick three. Or four."],
+      selected(&Matcher::insensitive("pick three.").with_boundary(Boundary::Line).inverted(true), contents)
+    );
+  }
+
+  #[test]
+  fn first_insensitive_match() {
+    let query = "rUst";
+    let contents = "\
+  Rust:
+  safe, fast, productive.
+  Pick three.
+  Duct tape.";
+        
+      assert_eq!(Some("Rust:"), insensitive(query).search(contents).next().map(|m| m.line))
+  }
+  
+  #[test]
+  fn first_sensitive_match() {
+    let query = "safe";
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+  
+    assert_eq!(Some("safe"), Searcher::new(query).search(contents).next().map(|m| m.matched()));
+  }
+
+  #[test]
+  fn matches_carry_positions() {
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+    let matches: Vec<Match> = insensitive("RUST").search(contents).collect();
+    assert_eq!(2, matches.len());
+    assert_eq!((1, 0, 0, 4), (matches[0].line_number, matches[0].line_offset, matches[0].start, matches[0].end));
+    assert_eq!((4, 42, 1, 5), (matches[1].line_number, matches[1].line_offset, matches[1].start, matches[1].end));
+    assert_eq!("rust", matches[1].matched());
+
+    let matches: Vec<Match> = Searcher::new("duct").search(contents).collect();
+    assert_eq!("safe, fast, productive.", matches[0].line);
+    assert_eq!(15, matches[0].start);
+  }
+
+  #[test]
+  fn max_count_and_laziness() {
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+    let searcher = Searcher::builder().pattern("t").max_count(Some(2)).build().unwrap();
+    assert_eq!(vec!["Rust:", "safe, fast, productive."], lines(&searcher, contents));
+    let searcher = Searcher::builder().pattern("e").invert(true).max_count(Some(1)).build().unwrap();
+    assert_eq!(vec!["Rust:"], lines(&searcher, contents));
+
+    //nothing past the first hit is looked at
+    let endless = "no\n".repeat(3) + "Rust\n" + &"x\n".repeat(1_000_000);
+    assert_eq!(4, Searcher::new("Rust").search(&endless).next().unwrap().line_number);
+  }
+
+  #[test]
+  fn several_patterns() {
+    let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+    let patterns = vec![String::from("three"), String::from("FAST")];
+
+    assert_eq!(vec!["Pick three."], selected(&Matcher::literals(&patterns, false), contents));
+    assert_eq!(vec!["safe, fast, productive.", "Pick three."], selected(&Matcher::literals(&patterns, true), contents));
+    assert_eq!(
+      vec!["Rust:", "Trust me."],
+      selected(&Matcher::literals(&patterns, true).inverted(true), contents)
+    );
+  }
+
+  #[test]
+  fn fuzzy_matches_report_their_distance() {
+    let contents = "\
+I'm nobody! Who are you?
+Are you nobdy, too?
+Then there's a pair of us - don't tell!";
+
+    let searcher = Searcher::builder().pattern("NOBODY").ignore_case(true).fuzzy(Some(1)).build().unwrap();
+    let matches: Vec<(usize, &str, usize)> = searcher.search(contents).map(|m| (m.line_number, m.matched(), m.distance)).collect();
+    assert_eq!(vec![(1, "nobody", 0), (2, "nobdy", 1)], matches);
+    assert_eq!(0, Searcher::new("you").search(contents).next().unwrap().distance);
+
+    let fuzzy_regex = Searcher::builder().pattern("nob.dy").regex(true).fuzzy(Some(1)).build();
+    assert!(matches!(fuzzy_regex, Err(Error::Args(ArgsError::Requires { .. }))));
+  }
+
+  #[test]
+  fn multiline_matches_span_lines() {
+    let contents = "\
+fn search<'a>(
+    query: &str,
+    contents: &'a str,
+) -> Vec<&'a str> {
+fn main() {";
+
+    let searcher = Searcher::builder().pattern(r"fn \w+(<[^>]*>)?\([^)]*\)").regex(true).multiline(true).build().unwrap();
+    let matches: Vec<(usize, usize, &str)> = searcher.search(contents).map(|m| (m.line_number, m.end_line_number(), m.matched())).collect();
+    assert_eq!(vec![(1, 4, "fn search<'a>(\n    query: &str,\n    contents: &'a str,\n)"), (5, 5, "fn main()")], matches);
+    assert_eq!("fn search<'a>(\n    query: &str,\n    contents: &'a str,\n) -> Vec<&'a str> {", searcher.search(contents).next().unwrap().line);
+    //without it, the pattern only finds what fits on one line
+    let lines = Searcher::builder().pattern(r"fn \w+(<[^>]*>)?\([^)]*\)").regex(true).build().unwrap();
+    assert_eq!(vec![5], lines.search(contents).map(|m| m.line_number).collect::<Vec<_>>());
+
+    let inverted = Searcher::builder().pattern("fn").multiline(true).invert(true).build();
+    assert!(matches!(inverted, Err(Error::Args(ArgsError::Requires { .. }))));
+  }
+
+  #[test]
+  fn find_all_spans() {
+    let mut spans = Vec::new();
+    Matcher::insensitive("to").find_all("To tell your name to", &mut spans);
+    assert_eq!(vec![(0, 2), (18, 20)], spans);
+
+    spans.clear();
+    Matcher::regex(Regex::build("o*", false).unwrap()).find_all("bog", &mut spans);
+    assert_eq!(vec![(0, 0), (1, 2), (3, 3)], spans);
+  }
+
+  #[test]
+  fn regex_search() {
+    let contents = "\
+To tell your name the livelong day
+To an admiring bog!
+How dreary to be somebody!";
+
+    let searcher = Searcher::builder().pattern("^To .* bog!$").regex(true).build().unwrap();
+    assert_eq!(vec!["To an admiring bog!"], lines(&searcher, contents));
+
+    let searcher = Searcher::builder().pattern("^to ").regex(true).ignore_case(true).build().unwrap();
+    assert_eq!(vec!["To tell your name the livelong day", "To an admiring bog!"], lines(&searcher, contents));
+    assert!(matches!(Searcher::builder().pattern("(to").regex(true).build(), Err(Error::Pattern(_))));
+  }
+  
+}
\ No newline at end of file
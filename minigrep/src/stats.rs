@@ -0,0 +1,83 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::output::Summary;
+
+// --stats: what a run went through and where its time went, printed on stderr once it's done so
+// the results on stdout stay as they are:
+//   12 files scanned (3 with matches)
+//   1048576 bytes read
+//   34567 lines examined
+//   120 matches found (on 100 lines)
+//   0.000120s building the matcher
+//   0.003402s walking the paths
+//   0.012305s searching
+//   0.015827s in total
+// One line per number, with the number first, so a script keeping an eye on regressions can cut
+// them out. The bytes are the ones read from the files, before they're decompressed or decoded;
+// the lines are the ones the search went through, by the matcher or, on the fast path for literal
+// queries, by memmem. The matches are all of them, not only the first on each line, which is all
+// most output needs.
+
+//how long each phase of a run took, in the order they ran
+pub struct Timer {
+  started: Instant,
+  lap: Instant, //when the phase being timed started
+  phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timer {
+  pub fn start() -> Timer {
+    let now = Instant::now();
+    Timer { started: now, lap: now, phases: Vec::new() }
+  }
+
+  //ends the phase that started with the last lap, or with start()
+  pub fn lap(&mut self, phase: &'static str) {
+    let now = Instant::now();
+    self.phases.push((phase, now - self.lap));
+    self.lap = now;
+  }
+
+  pub fn phases(&self) -> &[(&'static str, Duration)] {
+    &self.phases
+  }
+
+  pub fn total(&self) -> Duration {
+    self.started.elapsed()
+  }
+}
+
+pub fn report(out: &mut impl Write, summary: &Summary, phases: &[(&str, Duration)], total: Duration) -> io::Result<()> {
+  let Summary { files_searched, files_matched, matched_lines, matches, bytes_read, lines_examined } = *summary;
+  writeln!(out, "{files_searched} files scanned ({files_matched} with matches)")?;
+  writeln!(out, "{bytes_read} bytes read")?;
+  writeln!(out, "{lines_examined} lines examined")?;
+  writeln!(out, "{matches} matches found (on {matched_lines} lines)")?;
+  for (phase, elapsed) in phases {
+    writeln!(out, "{:.6}s {phase}", elapsed.as_secs_f64())?;
+  }
+  writeln!(out, "{:.6}s in total", total.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn one_number_a_line() {
+    let summary = Summary { files_searched: 12, files_matched: 3, matched_lines: 100, matches: 120, bytes_read: 1 << 20, lines_examined: 34567 };
+    let phases = [("walking the paths", Duration::from_micros(3402)), ("searching", Duration::from_millis(12))];
+    let mut out = Vec::new();
+    report(&mut out, &summary, &phases, Duration::from_micros(15827)).unwrap();
+    let expected = "12 files scanned (3 with matches)\n1048576 bytes read\n34567 lines examined\n120 matches found (on 100 lines)\n\
+                    0.003402s walking the paths\n0.012000s searching\n0.015827s in total\n";
+    assert_eq!(expected, String::from_utf8(out).unwrap());
+
+    let mut timer = Timer::start();
+    timer.lap("building the matcher");
+    timer.lap("searching");
+    assert_eq!(vec!["building the matcher", "searching"], timer.phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>());
+    assert!(timer.total() >= timer.phases.iter().map(|(_, elapsed)| *elapsed).sum());
+  }
+}
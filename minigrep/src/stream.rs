@@ -0,0 +1,257 @@
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, Read};
+use std::rc::Rc;
+use std::str;
+
+use crate::Matcher;
+
+//reads a file (or stdin) one line at a time into a single reused buffer, so memory use depends
+//on the longest line instead of the size of the whole input
+pub struct LineReader<R> {
+  reader: R,
+  buf: Vec<u8>,
+  lossy: String, //the line with its invalid UTF-8 replaced, when it has some
+  line_number: usize,
+  offset: usize,
+}
+
+impl<R: BufRead> LineReader<R> {
+  pub fn new(reader: R) -> LineReader<R> {
+    LineReader { reader, buf: Vec::new(), lossy: String::new(), line_number: 0, offset: 0 }
+  }
+
+  //the next line as (line number counted from 1, byte offset of the line in the input, text),
+  //split the same way as str::lines(); Ok(None) once the input is exhausted.
+  //Bytes that aren't valid UTF-8 come out as U+FFFD, like String::from_utf8_lossy() does, so one
+  //stray Latin-1 byte doesn't make a whole file unsearchable
+  pub fn next_line(&mut self) -> io::Result<Option<(usize, usize, &str)>> {
+    self.buf.clear();
+    let read = self.reader.read_until(b'\n', &mut self.buf)?;
+    if read == 0 {
+      return Ok(None);
+    }
+    let offset = self.offset;
+    self.offset += read;
+    self.line_number += 1;
+
+    let mut bytes = &self.buf[..];
+    if let Some(rest) = bytes.strip_suffix(b"\n") {
+      bytes = rest.strip_suffix(b"\r").unwrap_or(rest);
+    }
+    let line = match str::from_utf8(bytes) {
+      Ok(line) => line,
+      Err(_) => {
+        self.lossy.clear();
+        for chunk in bytes.utf8_chunks() {
+          self.lossy.push_str(chunk.valid());
+          if !chunk.invalid().is_empty() {
+            self.lossy.push(char::REPLACEMENT_CHARACTER);
+          }
+        }
+        &self.lossy
+      }
+    };
+    Ok(Some((self.line_number, offset, line)))
+  }
+}
+
+//counts the bytes read through it, for --stats; the count is shared, so it can still be looked at
+//once the reader is wrapped in the decoders
+pub struct Counted<R> {
+  reader: R,
+  pub count: Rc<Cell<u64>>,
+}
+
+impl<R: BufRead> Counted<R> {
+  pub fn new(reader: R) -> Counted<R> {
+    Counted { reader, count: Rc::new(Cell::new(0)) }
+  }
+}
+
+impl<R: BufRead> Read for Counted<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.reader.read(buf)?;
+    self.count.set(self.count.get() + read as u64);
+    Ok(read)
+  }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    self.reader.fill_buf()
+  }
+
+  fn consume(&mut self, amount: usize) {
+    self.count.set(self.count.get() + amount as u64);
+    self.reader.consume(amount);
+  }
+}
+
+//looks at the start of the input for a byte order mark: UTF-16 text is turned into UTF-8 as it is
+//read, and a UTF-8 mark is dropped so it doesn't end up in front of the first line.
+//Byte offsets in the results are offsets in the UTF-8 text, not in the UTF-16 file
+pub fn decode<'a>(mut reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn BufRead + 'a>> {
+  let big_endian = match reader.fill_buf()? {
+    [0xEF, 0xBB, 0xBF, ..] => {
+      reader.consume(3);
+      return Ok(reader);
+    }
+    [0xFF, 0xFE, ..] => false,
+    [0xFE, 0xFF, ..] => true,
+    _ => return Ok(reader),
+  };
+  reader.consume(2);
+  let decoder = Utf16Decoder { inner: reader, big_endian, undecoded: Vec::new(), decoded: Vec::new(), read: 0 };
+  Ok(Box::new(BufReader::new(decoder)))
+}
+
+//whether decode() would do something with input that starts like this
+pub fn has_byte_order_mark(start: &[u8]) -> bool {
+  matches!(start, [0xEF, 0xBB, 0xBF, ..] | [0xFF, 0xFE, ..] | [0xFE, 0xFF, ..])
+}
+
+//UTF-16 in, UTF-8 out, a chunk at a time
+struct Utf16Decoder<R> {
+  inner: R,
+  big_endian: bool,
+  undecoded: Vec<u8>, //an odd byte or a lone high surrogate whose other half is in the next chunk
+  decoded: Vec<u8>,
+  read: usize, //how much of `decoded` was handed out already
+}
+
+impl<R: BufRead> Read for Utf16Decoder<R> {
+  fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+    while self.read == self.decoded.len() {
+      self.decoded.clear();
+      self.read = 0;
+      let chunk = self.inner.fill_buf()?;
+      if chunk.is_empty() {
+        if self.undecoded.is_empty() {
+          return Ok(0);
+        }
+        //the input ended halfway through a character
+        self.undecoded.clear();
+        self.decoded.extend_from_slice("\u{FFFD}".as_bytes());
+        break;
+      }
+      let len = chunk.len();
+      self.undecoded.extend_from_slice(chunk);
+      self.inner.consume(len);
+
+      let big_endian = self.big_endian;
+      let mut units: Vec<u16> = self
+        .undecoded
+        .chunks_exact(2)
+        .map(|pair| match big_endian {
+          true => u16::from_be_bytes([pair[0], pair[1]]),
+          false => u16::from_le_bytes([pair[0], pair[1]]),
+        })
+        .collect();
+      let mut keep = self.undecoded.len() % 2;
+      if units.last().is_some_and(|unit| (0xD800..0xDC00).contains(unit)) {
+        units.pop();
+        keep += 2;
+      }
+      self.undecoded.drain(..self.undecoded.len() - keep);
+      for c in char::decode_utf16(units) {
+        let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.decoded.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+      }
+    }
+    let len = out.len().min(self.decoded.len() - self.read);
+    out[..len].copy_from_slice(&self.decoded[self.read..self.read + len]);
+    self.read += len;
+    Ok(len)
+  }
+}
+
+//a hit found while streaming; it owns its line because the read buffer is reused for the next one
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineMatch {
+  pub line_number: usize,
+  pub line_offset: usize,
+  pub line: String,
+  pub start: usize,
+  pub end: usize,
+}
+
+pub struct StreamSearch<'m, R> {
+  matcher: &'m Matcher,
+  lines: LineReader<R>,
+}
+
+impl<R: BufRead> Iterator for StreamSearch<'_, R> {
+  type Item = io::Result<LineMatch>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      let (line_number, line_offset, line) = match self.lines.next_line() {
+        Ok(Some(line)) => line,
+        Ok(None) => return None,
+        Err(e) => return Some(Err(e)),
+      };
+      //same rule as Searcher::search(): inverted hits have no span, so they get an empty one
+      let found = self.matcher.find(line);
+      if found.is_some() != self.matcher.is_inverted() {
+        let (start, end) = found.unwrap_or((0, 0));
+        return Some(Ok(LineMatch { line_number, line_offset, line: line.to_string(), start, end }));
+      }
+    }
+  }
+}
+
+//the streaming counterpart of Searcher::search(): matches come out as soon as their line is read
+pub fn search_stream<R: BufRead>(matcher: &Matcher, reader: R) -> StreamSearch<'_, R> {
+  StreamSearch { matcher, lines: LineReader::new(reader) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+
+  #[test]
+  fn streams_matches_with_positions() {
+    let input = Cursor::new("Rust:\r\nsafe, fast, productive.\nPick three.\nTrust me.");
+    let matches: Vec<LineMatch> = search_stream(&Matcher::insensitive("rust"), input).collect::<io::Result<_>>().unwrap();
+
+    assert_eq!(2, matches.len());
+    assert_eq!("Rust:", matches[0].line);
+    assert_eq!((4, 43, 1, 5), (matches[1].line_number, matches[1].line_offset, matches[1].start, matches[1].end));
+  }
+
+  #[test]
+  fn invalid_utf8_is_replaced() {
+    let input = Cursor::new(b"fine\ncaf\xe9 \xff\xfe\nagain\n".to_vec());
+    let mut lines = LineReader::new(input);
+
+    assert_eq!(Some((1, 0, "fine")), lines.next_line().unwrap());
+    assert_eq!(Some((2, 5, "caf\u{FFFD} \u{FFFD}\u{FFFD}")), lines.next_line().unwrap());
+    assert_eq!(Some((3, 13, "again")), lines.next_line().unwrap());
+  }
+
+  #[test]
+  fn byte_order_marks() {
+    let read_all = |bytes: Vec<u8>| {
+      let mut text = String::new();
+      //small chunks, so characters get split between them
+      decode(Box::new(BufReader::with_capacity(3, Cursor::new(bytes)))).unwrap().read_to_string(&mut text).unwrap();
+      text
+    };
+    let utf16 = |text: &str, big_endian: bool| {
+      let mut bytes = if big_endian { vec![0xFE, 0xFF] } else { vec![0xFF, 0xFE] };
+      for unit in text.encode_utf16() {
+        bytes.extend(if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() });
+      }
+      bytes
+    };
+
+    let text = "Rust:\nsafe, fast 🦀\n";
+    assert_eq!(text, read_all(utf16(text, false)));
+    assert_eq!(text, read_all(utf16(text, true)));
+    assert_eq!(text, read_all([&[0xEF, 0xBB, 0xBF][..], text.as_bytes()].concat()));
+    assert_eq!(text, read_all(text.as_bytes().to_vec()));
+    //half a surrogate pair at the very end
+    assert_eq!("a\u{FFFD}", read_all(vec![0xFF, 0xFE, b'a', 0, 0x3D, 0xD8]));
+  }
+}
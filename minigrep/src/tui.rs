@@ -0,0 +1,479 @@
+use std::env;
+use std::error::Error as _;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+
+use crate::decompress;
+use crate::error::Error;
+use crate::output::Summary;
+use crate::stream;
+use crate::Searcher;
+
+// --tui: the hits in a full-screen list instead of printed once. The files are read into memory
+// up front, and every key typed into the query runs the search again over what was read, so the
+// list narrows down (or grows) as the query is refined. Enter opens the selected hit in $VISUAL or
+// $EDITOR at its line, and the file is read again when the editor exits.
+// Keys: typing edits the query, Backspace and Ctrl-U delete from it, Up/Down (or Ctrl-P/Ctrl-N),
+// PageUp/PageDown, Home/End move through the hits, Enter opens one, Esc or Ctrl-C quits.
+// Browser has everything but the terminal, so the tests can type into it and look at the screen.
+
+//the list stops here, a query of one letter shouldn't take a million lines to show
+const MAX_HITS: usize = 10_000;
+
+//the same colours as the printed output; they're undone with their own codes rather than a
+//full reset, so the selected row stays in reverse video around them
+const MATCH_COLOR: (&str, &str) = ("\x1b[1;31m", "\x1b[22;39m");
+const PATH_COLOR: (&str, &str) = ("\x1b[35m", "\x1b[39m");
+const LINE_NUMBER_COLOR: (&str, &str) = ("\x1b[32m", "\x1b[39m");
+const DIM: (&str, &str) = ("\x1b[2m", "\x1b[22m");
+const SELECTED: (&str, &str) = ("\x1b[7m", "\x1b[27m");
+
+//a file as it was read for browsing
+#[derive(Debug, Clone)]
+pub struct Loaded {
+  pub path: PathBuf, //"-" for standard input
+  pub contents: String,
+}
+
+//where a hit is: the file it's in, and its line as offsets into the file's contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hit {
+  file: usize,
+  line_number: usize,
+  line_offset: usize,
+  start: usize, //of the match, in the line
+  end: usize,
+}
+
+//what the terminal loop has to do after a key
+#[derive(Debug, PartialEq)]
+pub enum Action {
+  Redraw,
+  Open(PathBuf, usize),
+  Quit,
+}
+
+//the Searcher for a query, with the other options from the command line
+type Build<'b> = Box<dyn Fn(&str) -> Result<Searcher, Error> + 'b>;
+
+pub struct Browser<'b> {
+  files: Vec<Loaded>,
+  build: Build<'b>,
+  query: String,
+  hits: Vec<Hit>,
+  truncated: bool, //there were more than MAX_HITS
+  selected: usize,
+  scroll: usize, //the hit at the top of the list
+  message: Option<String>, //an invalid regex, or why the editor couldn't be opened
+}
+
+impl<'b> Browser<'b> {
+  pub fn new(files: Vec<Loaded>, query: &str, build: impl Fn(&str) -> Result<Searcher, Error> + 'b) -> Browser<'b> {
+    let mut browser = Browser {
+      files,
+      build: Box::new(build),
+      query: query.to_string(),
+      hits: Vec::new(),
+      truncated: false,
+      selected: 0,
+      scroll: 0,
+      message: None,
+    };
+    browser.search();
+    browser
+  }
+
+  //runs the query over every file again; a query that doesn't build (half a regex, say) keeps
+  //the hits of the last one that did, with the reason instead of the count
+  fn search(&mut self) {
+    self.message = None;
+    if self.query.is_empty() {
+      self.hits.clear();
+      return;
+    }
+    let searcher = match (self.build)(&self.query) {
+      Ok(searcher) => searcher,
+      Err(e) => {
+        //what was wrong with the query is enough for the status line, it's the query that's being built
+        self.message = Some(e.source().map_or_else(|| e.to_string(), |source| source.to_string()));
+        return;
+      }
+    };
+    self.hits.clear();
+    self.truncated = false;
+    for (file, loaded) in self.files.iter().enumerate() {
+      for m in searcher.search(&loaded.contents) {
+        if self.hits.len() == MAX_HITS {
+          self.truncated = true;
+          break;
+        }
+        self.hits.push(Hit { file, line_number: m.line_number, line_offset: m.line_offset, start: m.start, end: m.end });
+      }
+    }
+    self.selected = 0;
+    self.scroll = 0;
+  }
+
+  //`page` is how many hits fit on the screen, for PageUp and PageDown
+  pub fn key(&mut self, key: KeyEvent, page: usize) -> Action {
+    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+    let last = self.hits.len().saturating_sub(1);
+    match key.code {
+      KeyCode::Esc => return Action::Quit,
+      KeyCode::Char('c') if control => return Action::Quit,
+      KeyCode::Char('u') if control => {
+        self.query.clear();
+        self.search();
+      }
+      KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+      KeyCode::Char('p') if control => self.selected = self.selected.saturating_sub(1),
+      KeyCode::Down => self.selected = (self.selected + 1).min(last),
+      KeyCode::Char('n') if control => self.selected = (self.selected + 1).min(last),
+      KeyCode::PageUp => self.selected = self.selected.saturating_sub(page.max(1)),
+      KeyCode::PageDown => self.selected = (self.selected + page.max(1)).min(last),
+      KeyCode::Home => self.selected = 0,
+      KeyCode::End => self.selected = last,
+      KeyCode::Enter => {
+        let Some(hit) = self.hits.get(self.selected) else { return Action::Redraw };
+        let path = &self.files[hit.file].path;
+        if path == Path::new("-") {
+          self.message = Some(String::from("standard input can't be opened in an editor"));
+          return Action::Redraw;
+        }
+        return Action::Open(path.clone(), hit.line_number);
+      }
+      KeyCode::Backspace if !self.query.is_empty() => {
+        self.query.pop();
+        self.search();
+      }
+      KeyCode::Char(c) if !control => {
+        self.query.push(c);
+        self.search();
+      }
+      _ => {}
+    }
+    Action::Redraw
+  }
+
+  //after the editor: the file may have changed, so it's read again and searched with the same query
+  pub fn reload(&mut self, path: &Path, decompress: bool) {
+    let selected = self.selected;
+    if let Some(loaded) = self.files.iter_mut().find(|loaded| loaded.path == path) {
+      match read_text(path, decompress) {
+        Ok(contents) => loaded.contents = contents,
+        Err(e) => self.message = Some(format!("{}: {e}", path.display())),
+      }
+    }
+    let message = self.message.take();
+    self.search();
+    self.message = self.message.take().or(message);
+    self.selected = selected.min(self.hits.len().saturating_sub(1));
+  }
+
+  pub fn set_message(&mut self, message: String) {
+    self.message = Some(message);
+  }
+
+  //the rows of the screen: the query, a status line, then as many hits as fit; every row is cut to
+  //`width` characters, and the list scrolls so the selected hit is on screen
+  pub fn render(&mut self, width: usize, height: usize) -> Vec<String> {
+    let page = height.saturating_sub(2);
+    if self.selected < self.scroll {
+      self.scroll = self.selected;
+    } else if page > 0 && self.selected >= self.scroll + page {
+      self.scroll = self.selected + 1 - page;
+    }
+
+    let mut rows = vec![fit(&[("> ", None), (&self.query, None)], width)];
+    let status = match &self.message {
+      Some(message) => message.clone(),
+      None if self.query.is_empty() => String::from("type to search"),
+      None => {
+        let files = count_files(&self.hits);
+        let more = if self.truncated { "+" } else { "" };
+        format!("{}{more} hits in {files} of {} files", self.hits.len(), self.files.len())
+      }
+    };
+    rows.push(fit(&[(&status, Some(DIM))], width));
+
+    for (index, hit) in self.hits.iter().enumerate().skip(self.scroll).take(page) {
+      let loaded = &self.files[hit.file];
+      //a -U hit shows the first of its lines
+      let line = loaded.contents[hit.line_offset..].lines().next().unwrap_or_default();
+      let (start, end) = (hit.start.min(line.len()), hit.end.min(line.len()));
+      let path = loaded.path.display().to_string();
+      let number = hit.line_number.to_string();
+      let prefix = path.chars().count() + number.chars().count() + 2;
+      //a match further along the line than the screen is wide is scrolled into view
+      let shown = line[..end].chars().count().saturating_sub(width.saturating_sub(prefix)).min(line[..start].chars().count());
+      let from = line.char_indices().nth(shown).map_or(line.len(), |(i, _)| i).min(start);
+      let row = fit(
+        &[
+          (&path, Some(PATH_COLOR)),
+          (":", None),
+          (&number, Some(LINE_NUMBER_COLOR)),
+          (":", None),
+          (&line[from..start], None),
+          (&line[start..end], Some(MATCH_COLOR)),
+          (&line[end..], None),
+        ],
+        width,
+      );
+      rows.push(match index == self.selected {
+        true => {
+          let padding = " ".repeat(width.saturating_sub(visible_len(&row)));
+          format!("{}{row}{padding}{}", SELECTED.0, SELECTED.1)
+        }
+        false => row,
+      });
+    }
+    rows.resize(height, String::new());
+    rows
+  }
+
+  //for the exit status: like a printed search, it's a success if the last query found something
+  pub fn summary(&self) -> Summary {
+    Summary {
+      files_searched: self.files.len(),
+      files_matched: count_files(&self.hits),
+      matched_lines: self.hits.len(),
+      matches: self.hits.len(),
+      ..Summary::default()
+    }
+  }
+}
+
+fn count_files(hits: &[Hit]) -> usize {
+  //the hits come file by file
+  hits.windows(2).filter(|pair| pair[0].file != pair[1].file).count() + usize::from(!hits.is_empty())
+}
+
+//the pieces of a row, coloured, cut off after `width` characters; tabs become spaces so the
+//terminal's idea of the width is the same as ours
+fn fit(pieces: &[(&str, Option<(&str, &str)>)], width: usize) -> String {
+  let mut row = String::new();
+  let mut left = width;
+  for &(text, color) in pieces {
+    let text: String = text.chars().map(|c| if c == '\t' { ' ' } else { c }).filter(|c| !c.is_control()).take(left).collect();
+    left -= text.chars().count();
+    match color {
+      Some((on, off)) if !text.is_empty() => row.push_str(&format!("{on}{text}{off}")),
+      _ => row.push_str(&text),
+    }
+  }
+  row
+}
+
+//the characters of a row that take up room on the screen, without the escape codes
+fn visible_len(row: &str) -> usize {
+  let mut len = 0;
+  let mut chars = row.chars();
+  while let Some(c) = chars.next() {
+    if c == '\x1b' {
+      chars.by_ref().find(|c| c.is_ascii_alphabetic());
+    } else {
+      len += 1;
+    }
+  }
+  len
+}
+
+//reads the files to browse; the ones that can't be read, and binary files unless -a asked for
+//them, are left out, and the errors given back to be reported once the screen is put back
+pub fn load(files: &[PathBuf], text: bool, decompress: bool) -> (Vec<Loaded>, Vec<Error>) {
+  let mut loaded = Vec::new();
+  let mut errors = Vec::new();
+  for path in files {
+    match read_text(path, decompress) {
+      Ok(contents) if text || !contents.contains('\0') => loaded.push(Loaded { path: path.clone(), contents }),
+      Ok(_) => {}
+      Err(e) => errors.push(Error::Read { path: path.clone(), source: e }),
+    }
+  }
+  (loaded, errors)
+}
+
+//a whole file as text, the same way the search reads it: decompressed with -z, UTF-16 decoded
+//and invalid UTF-8 replaced
+fn read_text(path: &Path, decompress: bool) -> io::Result<String> {
+  let reader: Box<dyn BufRead> = match path == Path::new("-") {
+    true => Box::new(io::stdin().lock()),
+    false => Box::new(BufReader::new(File::open(path)?)),
+  };
+  let reader = match decompress {
+    true => decompress::decompress(reader)?,
+    false => reader,
+  };
+  let mut bytes = Vec::new();
+  stream::decode(reader)?.read_to_end(&mut bytes)?;
+  Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+//takes over the terminal until the browser is quit, and puts it back however that happens
+pub fn browse(browser: &mut Browser, decompress: bool) -> Result<(), Error> {
+  let mut out = io::stdout();
+  terminal::enable_raw_mode().map_err(Error::Write)?;
+  let shown = execute!(out, terminal::EnterAlternateScreen, cursor::Hide).and_then(|_| run(browser, decompress, &mut out));
+  let restored = execute!(out, cursor::Show, terminal::LeaveAlternateScreen).and_then(|_| terminal::disable_raw_mode());
+  shown.and(restored).map_err(Error::Write)
+}
+
+fn run(browser: &mut Browser, decompress: bool, out: &mut impl Write) -> io::Result<()> {
+  loop {
+    let (width, height) = terminal::size()?;
+    let rows = browser.render(width as usize, height as usize);
+    write!(out, "{}", cursor::MoveTo(0, 0))?;
+    for (i, row) in rows.iter().enumerate() {
+      write!(out, "{}{row}{}", cursor::MoveTo(0, i as u16), terminal::Clear(terminal::ClearType::UntilNewLine))?;
+    }
+    out.flush()?;
+
+    let key = match event::read()? {
+      //only presses: some terminals report releases too
+      Event::Key(key) if key.kind != KeyEventKind::Release => key,
+      _ => continue, //a resize is drawn on the next pass
+    };
+    match browser.key(key, (height as usize).saturating_sub(2)) {
+      Action::Quit => return Ok(()),
+      Action::Redraw => {}
+      Action::Open(path, line) => {
+        //the editor gets the terminal the way it expects to find it
+        execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        let status = open_in_editor(&path, line);
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        browser.reload(&path, decompress);
+        match status {
+          Ok(status) if !status.success() => browser.set_message(format!("the editor exited with {status}")),
+          Ok(_) => {}
+          Err(e) => browser.set_message(format!("can't start the editor: {e}")),
+        }
+      }
+    }
+  }
+}
+
+//$VISUAL, then $EDITOR, then vi, which can have arguments of its own ("code --wait")
+fn open_in_editor(path: &Path, line: usize) -> io::Result<std::process::ExitStatus> {
+  let editor = ["VISUAL", "EDITOR"].iter().filter_map(env::var_os).find(|editor| !editor.is_empty()).unwrap_or_else(|| OsString::from("vi"));
+  let editor = editor.to_string_lossy();
+  let mut words = editor.split_whitespace();
+  let program = words.next().unwrap_or("vi");
+  Command::new(program).args(words).args(editor_args(program, path, line)).status()
+}
+
+//how to open a file at a line: most editors take +LINE before the file (vi, vim, nvim, nano,
+//emacs, micro, kak), the others want FILE:LINE
+fn editor_args(program: &str, path: &Path, line: usize) -> Vec<OsString> {
+  let name = Path::new(program).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+  let at_line = || OsString::from(format!("{}:{line}", path.display()));
+  match name.as_str() {
+    "code" | "codium" | "code-insiders" => vec![OsString::from("-g"), at_line()],
+    "subl" | "zed" | "hx" | "helix" => vec![at_line()],
+    _ => vec![OsString::from(format!("+{line}")), path.as_os_str().to_os_string()],
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn files() -> Vec<Loaded> {
+    vec![
+      Loaded { path: PathBuf::from("poem.txt"), contents: String::from(include_str!("../poem.txt")) },
+      Loaded { path: PathBuf::from("book.txt"), contents: String::from("Rust:\nsafe, fast, productive.\n\tTrust me.") },
+    ]
+  }
+
+  fn browser<'b>() -> Browser<'b> {
+    Browser::new(files(), "ru", |query: &str| Searcher::builder().pattern(query).ignore_case(true).build())
+  }
+
+  fn press(browser: &mut Browser, keys: &str) {
+    for c in keys.chars() {
+      let code = match c {
+        '<' => KeyCode::Backspace,
+        'v' => KeyCode::Down,
+        '^' => KeyCode::Up,
+        c => KeyCode::Char(c),
+      };
+      assert_eq!(Action::Redraw, browser.key(KeyEvent::from(code), 10));
+    }
+  }
+
+  //the rows as they read on the screen, without the colours and the padding of the selected row
+  fn plain(rows: &[String]) -> Vec<String> {
+    let strip = |row: &String| {
+      let mut text = String::new();
+      let mut chars = row.chars();
+      while let Some(c) = chars.next() {
+        match c {
+          '\x1b' => _ = chars.by_ref().find(|c| c.is_ascii_alphabetic()),
+          c => text.push(c),
+        }
+      }
+      text.trim_end().to_string()
+    };
+    rows.iter().map(strip).collect()
+  }
+
+  #[test]
+  fn the_list_follows_the_query() {
+    let mut browser = browser();
+    assert_eq!(vec!["> ru", "2 hits in 1 of 2 files", "book.txt:1:Rust:", "book.txt:3: Trust me.", ""], plain(&browser.render(40, 5)));
+
+    //typed letters narrow the search down, Backspace widens it again
+    press(&mut browser, "st m");
+    assert_eq!(vec!["> rust m", "1 hits in 1 of 2 files", "book.txt:3: Trust me."], plain(&browser.render(40, 3)));
+    press(&mut browser, "<<<<<o");
+    assert_eq!("2 hits in 2 of 2 files", plain(&browser.render(40, 3))[1]);
+
+    //a regex that doesn't build yet keeps the last hits and says why
+    let mut regex = Browser::new(files(), "bog", |query: &str| Searcher::builder().pattern(query).regex(true).build());
+    press(&mut regex, "(");
+    let rows = plain(&regex.render(80, 3));
+    assert!(rows[1].starts_with("invalid regex"));
+    assert_eq!("poem.txt:9:To an admiring bog!", rows[2]);
+  }
+
+  #[test]
+  fn moving_through_the_hits_and_opening_one() {
+    let mut browser = Browser::new(files(), "o", |query: &str| Searcher::builder().pattern(query).build());
+    //the list scrolls to keep the selected hit on the screen: two rows of hits fit in four
+    press(&mut browser, "vvv");
+    let rows = browser.render(30, 4);
+    assert!(rows[3].starts_with(SELECTED.0) && plain(&rows)[3] == "poem.txt:4:hey'd banish us, yo");
+    assert_eq!(30, visible_len(&rows[3])); //the selection goes all the way across
+    assert_eq!(Action::Open(PathBuf::from("poem.txt"), 4), browser.key(KeyEvent::from(KeyCode::Enter), 2));
+    press(&mut browser, "^^^^^^");
+    assert_eq!(Action::Open(PathBuf::from("poem.txt"), 1), browser.key(KeyEvent::from(KeyCode::Enter), 2));
+    assert_eq!(Action::Quit, browser.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), 2));
+
+    let summary = browser.summary();
+    assert_eq!((2, 2), (summary.files_searched, summary.files_matched));
+  }
+
+  #[test]
+  fn long_lines_are_cut_around_the_match() {
+    let line = format!("{}needle{}", "x".repeat(100), "y".repeat(100));
+    let files = vec![Loaded { path: PathBuf::from("a"), contents: line }];
+    let mut browser = Browser::new(files, "needle", |query: &str| Searcher::builder().pattern(query).build());
+    let row = &plain(&browser.render(20, 3))[2];
+    assert_eq!("a:1:xxxxxxxxxxneedle", row);
+  }
+
+  #[test]
+  fn editors_get_the_line_their_way() {
+    let path = Path::new("src/lib.rs");
+    assert_eq!(vec!["+12", "src/lib.rs"], editor_args("/usr/bin/vim", path, 12));
+    assert_eq!(vec!["-g", "src/lib.rs:12"], editor_args("code", path, 12));
+    assert_eq!(vec!["src/lib.rs:12"], editor_args("hx", path, 12));
+  }
+}
@@ -0,0 +1,242 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::decompress::Format;
+use crate::error::Error;
+use crate::glob::{self, Glob};
+use crate::ignore::{self, IgnoreFile};
+
+//what a directory walk leaves out; the defaults skip what a search of a source tree doesn't want
+//to see: ignored files, hidden files and binaries
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+  pub hidden: bool, //--hidden: also search files and directories whose name starts with '.'
+  pub no_ignore: bool, //--no-ignore: don't read .gitignore and .ignore files
+  pub text: bool, //-a: also search files that look binary
+  pub decompress: bool, //-z: compressed files are searched for what's in them, they aren't binary
+  pub include: Vec<Glob>, //--include: when given, only files matching one of these are searched
+  pub exclude: Vec<Glob>, //--exclude: files and directories matching one of these are skipped
+}
+
+//how much of a file is read to tell whether it's binary
+const BINARY_SNIFF: u64 = 8 * 1024;
+
+//what a walk found
+#[derive(Debug, Default)]
+pub struct Walked {
+  pub files: Vec<PathBuf>,
+  pub dirs: Vec<PathBuf>, //every directory the walk went into, for --watch to keep an eye on
+  pub unreadable: usize, //directories and entries that couldn't be read, already reported
+}
+
+impl Walked {
+  //like an unreadable file in the search, a directory that can't be read is reported and skipped,
+  //and the run still ends with the exit status for unreadable files
+  fn skip(&mut self, e: Error) {
+    eprintln!("minigrep: {}", e.chain());
+    self.unreadable += 1;
+  }
+}
+
+//expands the paths given on the command line into the list of files to search:
+//plain files are kept as they are and directories are walked recursively.
+//Files named on the command line are searched even if they're hidden, ignored or binary,
+//only --include and --exclude apply to them.
+pub fn collect_files(paths: &[String], options: &WalkOptions) -> Walked {
+  let mut walked = Walked::default();
+  for path in paths {
+    let path = Path::new(path);
+    if path.is_dir() {
+      let root = match fs::canonicalize(path) {
+        Ok(root) => root,
+        Err(e) => {
+          walked.skip(Error::read(path)(e));
+          continue;
+        }
+      };
+      let mut ignores = match options.no_ignore {
+        true => Vec::new(),
+        false => ignore_files_above(&root),
+      };
+      let walk = Walk { options, root: &root };
+      walk.dir(path, &root, &mut ignores, &mut walked);
+    } else if path == Path::new("-") || options.selects(&path.to_string_lossy()) {
+      //anything that isn't a directory is handed to the search as is, so a missing file
+      //is reported by the read in run() like before
+      walked.files.push(path.to_path_buf());
+    }
+  }
+  walked
+}
+
+//the ignore files of the directories above `root` that are in the same git repository,
+//outermost first; outside a repository only the ones inside the searched directory count
+fn ignore_files_above(root: &Path) -> Vec<IgnoreFile> {
+  let Some(repo) = root.ancestors().find(|dir| dir.join(".git").exists()) else {
+    return Vec::new();
+  };
+  let mut files = Vec::new();
+  for dir in root.ancestors().skip(1).take_while(|dir| dir.starts_with(repo)) {
+    files.extend(IgnoreFile::read(dir, ".ignore"));
+    files.extend(IgnoreFile::read(dir, ".gitignore"));
+  }
+  files.reverse();
+  files
+}
+
+struct Walk<'a> {
+  options: &'a WalkOptions,
+  root: &'a Path, //the canonical form of the directory given on the command line
+}
+
+impl Walk<'_> {
+  //`dir` is the directory as it will be shown, `real` its canonical path for the ignore files;
+  //`ignores` holds the ignore files of the directories above, outermost first
+  fn dir(&self, dir: &Path, real: &Path, ignores: &mut Vec<IgnoreFile>, walked: &mut Walked) {
+    let entries = fs::read_dir(dir).and_then(|entries| entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>());
+    let mut entries = match entries {
+      Ok(entries) => entries,
+      Err(e) => {
+        walked.skip(Error::read(dir)(e));
+        return;
+      }
+    };
+    entries.sort(); //read_dir order depends on the platform, sorting keeps the output stable between runs
+    walked.dirs.push(dir.to_path_buf());
+
+    //.ignore goes after .gitignore so it's asked first and can override it
+    let outer = ignores.len();
+    if !self.options.no_ignore {
+      ignores.extend(IgnoreFile::read(real, ".gitignore"));
+      ignores.extend(IgnoreFile::read(real, ".ignore"));
+    }
+
+    for name in entries {
+      let (entry, real_entry) = (dir.join(&name), real.join(&name));
+      if !self.options.hidden && name.to_string_lossy().starts_with('.') {
+        continue;
+      }
+      //symlink_metadata doesn't follow links, so a link pointing back up the tree can't loop forever
+      let file_type = match fs::symlink_metadata(&entry) {
+        Ok(metadata) => metadata.file_type(),
+        Err(e) => {
+          walked.skip(Error::read(&entry)(e));
+          continue;
+        }
+      };
+      let is_dir = file_type.is_dir();
+      if !self.options.no_ignore && ignore::is_ignored(ignores, &real_entry, is_dir) {
+        continue;
+      }
+      let relative = glob::relative_path(self.root, &real_entry).unwrap_or_default();
+      if is_dir {
+        if !self.options.excludes_dir(&relative) {
+          self.dir(&entry, &real_entry, ignores, walked);
+        }
+      } else if file_type.is_file() && self.options.selects(&relative) && (self.options.text || !looks_binary(&entry, self.options.decompress)) {
+        walked.files.push(entry);
+      }
+    }
+    ignores.truncate(outer);
+  }
+}
+
+impl WalkOptions {
+  //whether a file passes --include and --exclude; `path` is relative to the searched directory,
+  //or as given on the command line
+  fn selects(&self, path: &str) -> bool {
+    let included = self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path, false));
+    included && !self.exclude.iter().any(|glob| glob.matches(path, false))
+  }
+
+  fn excludes_dir(&self, path: &str) -> bool {
+    self.exclude.iter().any(|glob| glob.matches(path, true) || glob.matches_everything_in(path))
+  }
+}
+
+//like git and grep: a NUL byte near the start of a file means it isn't text. A compressed file
+//searched with -z gets the same test after it's decompressed, in the search
+fn looks_binary(path: &Path, decompress: bool) -> bool {
+  let mut start = Vec::new();
+  match File::open(path) {
+    Ok(file) => {
+      file.take(BINARY_SNIFF).read_to_end(&mut start).is_ok() && start.contains(&0) && !(decompress && Format::detect(&start).is_some())
+    }
+    Err(_) => false, //let the search report why it can't be read
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn walks_directories_recursively_in_sorted_order() {
+    let root = std::env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+    fs::create_dir_all(root.join("b/nested")).unwrap();
+    fs::write(root.join("b/nested/deep.txt"), "deep").unwrap();
+    fs::write(root.join("b/top.txt"), "top").unwrap();
+    fs::write(root.join("a.txt"), "a").unwrap();
+
+    let walked = collect_files(&[root.display().to_string(), String::from("missing.txt")], &WalkOptions::default());
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(
+      vec![root.join("a.txt"), root.join("b/nested/deep.txt"), root.join("b/top.txt"), PathBuf::from("missing.txt")],
+      walked.files
+    );
+    assert_eq!(vec![root.clone(), root.join("b"), root.join("b/nested")], walked.dirs);
+    assert_eq!(0, walked.unreadable);
+  }
+
+  #[test]
+  fn a_directory_that_cant_be_read_is_counted_and_skipped() {
+    let root = std::env::temp_dir().join(format!("minigrep-unreadable-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("a.txt"), "a").unwrap();
+
+    //gone between the listing of its parent and the walk into it, as a directory without read
+    //permission would be (which the tests can't count on, they may run as root)
+    let options = WalkOptions::default();
+    let walk = Walk { options: &options, root: &root };
+    let mut walked = Walked::default();
+    walk.dir(&root.join("gone"), &root.join("gone"), &mut Vec::new(), &mut walked);
+    walk.dir(&root, &root, &mut Vec::new(), &mut walked);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec![root.join("a.txt")], walked.files);
+    assert_eq!(vec![root.clone()], walked.dirs);
+    assert_eq!(1, walked.unreadable);
+  }
+
+  #[test]
+  fn skips_ignored_hidden_and_binary_files() {
+    let root = std::env::temp_dir().join(format!("minigrep-filter-{}", std::process::id()));
+    fs::create_dir_all(root.join("target/debug")).unwrap();
+    fs::create_dir_all(root.join("src/.cache")).unwrap();
+    fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+    fs::write(root.join("src/.ignore"), "!keep.log\n").unwrap();
+    for file in ["target/debug/out.rs", "src/.cache/c.rs", "src/a.rs", "src/b.txt", "src/x.log", "src/keep.log", "src/.hidden.rs"] {
+      fs::write(root.join(file), "text").unwrap();
+    }
+    fs::write(root.join("src/blob.rs"), b"ELF\0\0").unwrap();
+    fs::write(root.join("src/c.gz"), b"\x1f\x8b\x08\0\0\0\0\0").unwrap();
+
+    let walk = |options: WalkOptions| {
+      let files = collect_files(&[root.display().to_string()], &options).files;
+      let files: Vec<String> = files.iter().map(|file| file.strip_prefix(&root).unwrap().display().to_string()).collect();
+      files
+    };
+    let default = walk(WalkOptions::default());
+    let everything = walk(WalkOptions { hidden: true, no_ignore: true, text: true, ..Default::default() });
+    let globs = walk(WalkOptions { include: vec![Glob::new("*.rs")], exclude: vec![Glob::new("src/b*")], text: true, ..Default::default() });
+    let compressed = walk(WalkOptions { decompress: true, ..Default::default() });
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec!["src/a.rs", "src/b.txt", "src/keep.log"], default);
+    assert_eq!(11, everything.len()); //the seven files, the blob, the gzip file and the two ignore files
+    assert_eq!(vec!["src/a.rs"], globs);
+    assert_eq!(vec!["src/a.rs", "src/b.txt", "src/c.gz", "src/keep.log"], compressed);
+  }
+}
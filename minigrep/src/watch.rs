@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::error::Error;
+use crate::output::{Printer, Summary};
+use crate::parallel::{self, Scratch};
+use crate::replace::Replacement;
+use crate::walk::{self, WalkOptions};
+use crate::Matcher;
+
+// --watch: `tail -F | grep` with the search's own matching. Every file is searched from its start
+// like without --watch, then followed: whatever is appended is searched as it's written, one
+// complete line at a time. inotify tells us which files changed, through a watch on each directory
+// they're in rather than on the files themselves, so a file that's renamed, deleted or created is
+// noticed too:
+// - a file that gets shorter than what was read of it was truncated, it's read again from the start;
+// - a path that names another file than the one being read was rotated (`mv app.log app.log.1` and a
+//   new app.log), what's left of the old file is read to its end, then the new one from its start;
+// - a file that appears in a directory being searched is followed from its start, if the walk would
+//   have searched it.
+// The line numbers are counted from the start of each file, and start over with it after a
+// truncation or rotation, which are told on stderr like tail does.
+
+//how much is read from a file at a time
+const CHUNK: usize = 64 * 1024;
+
+//a file being followed, and how far it's been read
+struct Followed {
+  path: PathBuf,
+  file: Option<File>, //None while there is no file at the path
+  id: Option<(u64, u64)>, //device and inode of `file`, to tell when the path names another file
+  offset: u64, //bytes read
+  line: (usize, u64), //number and byte offset of the line being read
+  partial: Vec<u8>, //what came after the last '\n', the start of a line still being written
+  printer: Printer, //one per file, so the context and -m carry on from one change to the next
+  done: bool, //-m: the file had its hits, the rest of it isn't read
+}
+
+pub struct Follower<'f> {
+  matcher: &'f Matcher,
+  replacement: Option<&'f Replacement>,
+  settings: &'f Printer, //the printer each file's own printer is made from
+  files: Vec<Followed>,
+  index: HashMap<PathBuf, usize>,
+  //files that were read under a path they're not at anymore, by device and inode, with how far
+  //they were read; app.log rotated to app.log.1 in a directory being searched isn't read twice
+  moved: HashMap<(u64, u64), (u64, (usize, u64))>,
+  finished: Summary, //of the printers of files that were started over
+  scratch: Scratch,
+}
+
+impl<'f> Follower<'f> {
+  pub fn new(matcher: &'f Matcher, replacement: Option<&'f Replacement>, settings: &'f Printer) -> Follower<'f> {
+    Follower { matcher, replacement, settings, files: Vec::new(), index: HashMap::new(), moved: HashMap::new(), finished: Summary::default(), scratch: Scratch::default() }
+  }
+
+  pub fn is_followed(&self, path: &Path) -> bool {
+    self.index.contains_key(path)
+  }
+
+  //starts following a file and reads what's already in it; a file that isn't there yet is
+  //read once it's created
+  pub fn add(&mut self, path: PathBuf, out: &mut impl Write) -> io::Result<()> {
+    if self.is_followed(&path) {
+      return Ok(());
+    }
+    let mut printer = self.settings.for_worker();
+    printer.start_file(&path);
+    let mut followed = Followed { path: path.clone(), file: None, id: None, offset: 0, line: (1, 0), partial: Vec::new(), printer, done: false };
+    match open(&path) {
+      Ok(Some((mut file, id))) => {
+        //a file that was already read under another name goes on from where that stopped
+        if let Some((offset, line)) = self.moved.remove(&id) {
+          match file.seek(SeekFrom::Start(offset)) {
+            Ok(_) => (followed.offset, followed.line) = (offset, line),
+            Err(e) => report(&path, e)?,
+          }
+        }
+        (followed.file, followed.id) = (Some(file), Some(id));
+      }
+      Ok(None) => eprintln!("minigrep: {}: not there yet, waiting for it", path.display()),
+      Err(e) => report(&path, e)?,
+    }
+    self.index.insert(path.clone(), self.files.len());
+    self.files.push(followed);
+    self.catch_up(&path, out)
+  }
+
+  //reads whatever there is to read at `path` since the last time; errors reading the file are
+  //reported and it's still followed, errors writing the hits are returned
+  pub fn catch_up(&mut self, path: &Path, out: &mut impl Write) -> io::Result<()> {
+    let Some(&i) = self.index.get(path) else { return Ok(()) };
+    if self.files[i].done {
+      return Ok(());
+    }
+    if self.files[i].file.is_some() {
+      match self.truncated(i) {
+        Ok(false) => {}
+        Ok(true) => {
+          eprintln!("minigrep: {}: file truncated", path.display());
+          self.start_over(i);
+        }
+        Err(e) => return report(path, e),
+      }
+      self.read_to_end(i, out)?;
+    }
+
+    //what's at the path now, if anything
+    let followed = &mut self.files[i];
+    let (file, id) = match open(path) {
+      Ok(opened) => opened.unzip(),
+      Err(e) => return report(path, e),
+    };
+    if id == followed.id {
+      return Ok(());
+    }
+    //the last line of the old file won't get its '\n' now
+    if !followed.partial.is_empty() {
+      let partial = std::mem::take(&mut followed.partial);
+      self.feed(i, &partial, out)?;
+    }
+    let followed = &mut self.files[i];
+    let had_file = followed.file.is_some();
+    if let Some(old) = followed.id {
+      self.moved.insert(old, (followed.offset, followed.line));
+    }
+    (followed.file, followed.id) = (file, id);
+    match (had_file, id.is_some()) {
+      (true, false) => eprintln!("minigrep: {}: file removed", followed.path.display()),
+      (true, true) => eprintln!("minigrep: {}: file replaced, following the new one", followed.path.display()),
+      _ => {}
+    }
+    if had_file {
+      self.start_over(i);
+    }
+    self.read_to_end(i, out)
+  }
+
+  //whether the file got shorter than what was read of it, and if so, it's read again from the start
+  fn truncated(&mut self, i: usize) -> io::Result<bool> {
+    let followed = &mut self.files[i];
+    let Some(file) = &mut followed.file else { return Ok(false) };
+    if file.metadata()?.len() >= followed.offset {
+      return Ok(false);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(true)
+  }
+
+  //after a truncation or a rotation the file is read from its start, with a new printer: the
+  //context of the old lines has nothing to do with the new ones
+  fn start_over(&mut self, i: usize) {
+    let followed = &mut self.files[i];
+    self.finished += followed.printer.summary();
+    followed.printer = self.settings.for_worker();
+    followed.printer.start_file(&followed.path);
+    (followed.offset, followed.line) = (0, (1, 0));
+    followed.partial.clear();
+  }
+
+  fn read_to_end(&mut self, i: usize, out: &mut impl Write) -> io::Result<()> {
+    let mut chunk = vec![0; CHUNK];
+    loop {
+      let followed = &mut self.files[i];
+      let Some(file) = &mut followed.file else { return Ok(()) };
+      let read = match file.read(&mut chunk) {
+        Ok(read) => read,
+        Err(e) => return report(&followed.path, e),
+      };
+      if read == 0 || followed.done {
+        return Ok(());
+      }
+      followed.offset += read as u64;
+      followed.partial.extend_from_slice(&chunk[..read]);
+
+      //every complete line goes to the search, the rest waits for its '\n'
+      let partial = std::mem::take(&mut followed.partial);
+      let mut start = 0;
+      while let Some(end) = memchr::memchr(b'\n', &partial[start..]).map(|at| start + at) {
+        self.feed(i, &partial[start..end], out)?;
+        start = end + 1;
+      }
+      self.files[i].partial = partial[start..].to_vec();
+    }
+  }
+
+  //one line to the search and the file's printer
+  fn feed(&mut self, i: usize, line: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let followed = &mut self.files[i];
+    if followed.done {
+      return Ok(());
+    }
+    let (number, offset) = followed.line;
+    followed.line = (number + 1, offset + line.len() as u64 + 1);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let text = String::from_utf8_lossy(line);
+    parallel::search_line(self.matcher, self.replacement, &mut followed.printer, (number, offset as usize, &text), out, &mut self.scratch)?;
+    followed.done = followed.printer.file_done();
+    Ok(())
+  }
+
+  pub fn summary(&self) -> Summary {
+    let mut summary = self.finished;
+    for followed in &self.files {
+      summary += followed.printer.summary();
+    }
+    summary
+  }
+}
+
+//the file at `path`, if there is one, and its device and inode; the metadata is the open file's,
+//so the file that's read is the one that was checked
+fn open(path: &Path) -> io::Result<Option<(File, (u64, u64))>> {
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(e),
+  };
+  let metadata = file.metadata()?;
+  Ok(Some((file, (metadata.dev(), metadata.ino()))))
+}
+
+//a file that can't be read is still followed, it may be readable after the next change
+fn report(path: &Path, e: io::Error) -> io::Result<()> {
+  eprintln!("minigrep: {}: {e}", path.display());
+  Ok(())
+}
+
+//what's watched: each directory, and the path from the command line it's in, when it's a directory
+//that was walked; a new file in one of those means the walk is done again for it
+struct Dirs {
+  watched: HashMap<WatchDescriptor, Vec<(PathBuf, Option<String>)>>,
+  seen: HashSet<PathBuf>,
+}
+
+const DIR_EVENTS: WatchMask = WatchMask::MODIFY
+  .union(WatchMask::CREATE)
+  .union(WatchMask::MOVED_TO)
+  .union(WatchMask::MOVED_FROM)
+  .union(WatchMask::DELETE)
+  .union(WatchMask::ONLYDIR);
+
+impl Dirs {
+  fn watch(&mut self, inotify: &mut Inotify, dir: PathBuf, root: Option<&String>) -> Result<(), Error> {
+    if !self.seen.insert(dir.clone()) {
+      return Ok(());
+    }
+    //the directory of a file named without one is the current one
+    let real = if dir.as_os_str().is_empty() { Path::new(".") } else { &dir };
+    let wd = inotify.watches().add(real, DIR_EVENTS).map_err(Error::read(real))?;
+    self.watched.entry(wd).or_default().push((dir, root.cloned()));
+    Ok(())
+  }
+}
+
+//searches the files, then follows them until there's nothing left to watch: every directory
+//was deleted, or writing the hits failed
+pub fn follow(matcher: &Matcher, replacement: Option<&Replacement>, printer: &Printer, paths: &[String], options: &WalkOptions, out: &mut impl Write) -> Result<Summary, Error> {
+  //the paths can't be watched without inotify, so its errors are put down to them
+  let watch_error = || Error::read(paths.join(" "));
+  let mut inotify = Inotify::init().map_err(watch_error())?;
+  let mut dirs = Dirs { watched: HashMap::new(), seen: HashSet::new() };
+  let mut follower = Follower::new(matcher, replacement, printer);
+
+  //the directories are watched before the files are read, so nothing written in between is missed
+  let mut files = Vec::new();
+  for path in paths {
+    if Path::new(path).is_dir() {
+      let walked = walk::collect_files(std::slice::from_ref(path), options);
+      for dir in walked.dirs {
+        dirs.watch(&mut inotify, dir, Some(path))?;
+      }
+      files.extend(walked.files);
+    } else {
+      let file = PathBuf::from(path);
+      dirs.watch(&mut inotify, file.parent().unwrap_or(Path::new("")).to_path_buf(), None)?;
+      files.extend(walk::collect_files(std::slice::from_ref(path), options).files);
+    }
+  }
+  for file in files {
+    follower.add(file, out).map_err(Error::Write)?;
+  }
+  out.flush().map_err(Error::Write)?;
+
+  let mut buffer = [0; 4096];
+  while !dirs.watched.is_empty() {
+    let events = inotify.read_events_blocking(&mut buffer).map_err(watch_error())?;
+    //the events of one read are gathered first, so a file written a hundred times is read once
+    let mut changed = Vec::new();
+    let mut walk_again = HashSet::new();
+    for event in events {
+      if event.mask.contains(EventMask::IGNORED) {
+        //the directory is gone; if it comes back, it's watched again
+        for (dir, _) in dirs.watched.remove(&event.wd).unwrap_or_default() {
+          dirs.seen.remove(&dir);
+        }
+        continue;
+      }
+      let (Some(name), Some(watched)) = (event.name, dirs.watched.get(&event.wd)) else { continue };
+      let created = event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO);
+      for (dir, root) in watched {
+        let path = dir.join(name);
+        if follower.is_followed(&path) {
+          changed.push(path);
+        } else if let (true, Some(root)) = (created, root) {
+          walk_again.insert(root.clone());
+        }
+      }
+    }
+    //the files that changed first, so a file rotated into a directory being searched is known to
+    //have been read when the walk finds it under its new name
+    changed.sort();
+    changed.dedup();
+    for path in changed {
+      follower.catch_up(&path, out).map_err(Error::Write)?;
+    }
+    for root in walk_again {
+      //a directory can be gone again by the time it's walked, the walk reports it and goes on
+      let walked = walk::collect_files(std::slice::from_ref(&root), options);
+      for dir in walked.dirs {
+        if let Err(e) = dirs.watch(&mut inotify, dir, Some(&root)) {
+          eprintln!("minigrep: {}", e.chain());
+        }
+      }
+      for file in walked.files {
+        follower.add(file, out).map_err(Error::Write)?;
+      }
+    }
+    out.flush().map_err(Error::Write)?;
+  }
+  Ok(follower.summary())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs::{self, OpenOptions};
+
+  fn append(path: &Path, text: &str) {
+    OpenOptions::new().append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+  }
+
+  #[test]
+  fn appended_lines_are_searched_through_truncation_and_rotation() {
+    let root = std::env::temp_dir().join(format!("minigrep-watch-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let log = root.join("app.log");
+    fs::write(&log, "start\nERROR one\n").unwrap();
+
+    let matcher = Matcher::sensitive("ERROR");
+    let printer = Printer::new(false, true, 0, 0);
+    let mut follower = Follower::new(&matcher, None, &printer);
+    let mut out = Vec::new();
+    let catch_up = |follower: &mut Follower, out: &mut Vec<u8>| {
+      out.clear();
+      follower.catch_up(&log, out).unwrap();
+      String::from_utf8(out.clone()).unwrap()
+    };
+
+    //what's in the file already, then what's added to it, a line once it's complete
+    follower.add(log.clone(), &mut out).unwrap();
+    assert_eq!("2:ERROR one\n", String::from_utf8(out.clone()).unwrap());
+    append(&log, "ERROR two\nERROR thr");
+    assert_eq!("3:ERROR two\n", catch_up(&mut follower, &mut out));
+    append(&log, "ee\r\n");
+    assert_eq!("4:ERROR three\n", catch_up(&mut follower, &mut out));
+    assert_eq!("", catch_up(&mut follower, &mut out));
+
+    //truncated: read again from the start
+    fs::write(&log, "ERROR four\n").unwrap();
+    assert_eq!("1:ERROR four\n", catch_up(&mut follower, &mut out));
+
+    //rotated: the end of the old file, then the new one from its start
+    append(&log, "ERROR five\nERROR six");
+    fs::rename(&log, root.join("app.log.1")).unwrap();
+    fs::write(&log, "ERROR seven\n").unwrap();
+    assert_eq!("2:ERROR five\n3:ERROR six\n1:ERROR seven\n", catch_up(&mut follower, &mut out));
+    //the old file under its new name is known to have been read
+    out.clear();
+    follower.add(root.join("app.log.1"), &mut out).unwrap();
+    append(&root.join("app.log.1"), "ERROR late\n");
+    follower.catch_up(&root.join("app.log.1"), &mut out).unwrap();
+    assert_eq!("4:ERROR late\n", String::from_utf8(out.clone()).unwrap());
+
+    //removed, and back later
+    fs::remove_file(&log).unwrap();
+    assert_eq!("", catch_up(&mut follower, &mut out));
+    fs::write(&log, "ok\nERROR eight\n").unwrap();
+    assert_eq!("2:ERROR eight\n", catch_up(&mut follower, &mut out));
+
+    assert_eq!(9, follower.summary().matched_lines);
+    fs::remove_dir_all(&root).unwrap();
+  }
+}